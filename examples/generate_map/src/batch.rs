@@ -0,0 +1,426 @@
+//! Headless map generation: `generate_map --batch [options]` produces maps without opening
+//! a window, so it can run on machines without a GPU.
+//!
+//! * `--width <tiles>`, `--height <tiles>` - map size, defaults to `DEFAULT_MAP_WIDTH/HEIGHT`
+//! * `--terrains land,grass,...` - names of the tileset terrains allowed in generation, all of them by default
+//! * `--weights grass=5,water=0.5,...` - relative weights of terrains, 1 for unlisted ones
+//! * `--heightmap` - make terrains follow a noise heightmap with default parameters
+//! * `--scatter` - scatter trees, bushes and stones over the generated terrain
+//! * `--seed <n>` - seed of the first map, every next map gets the seed increased by one
+//! * `--count <n>` - amount of maps to generate, one by default
+//! * `--out <dir>` - output directory, the current one by default
+//! * `--tileset <path>` - tileset description to use instead of `assets/tileset.ron`
+//! * `--thumbnail <px>` - also write a `map_<seed>_thumb.png` whose longer side fits into `px`
+//!
+//! Every map is written as `map_<seed>.orommap` along with a `map_<seed>.png` preview.
+//!
+//! `generate_map --render <map.orommap> [--out <image.png>] [--thumbnail <px>]` renders
+//! an existing map into a PNG at 32 pixels per tile, next to the map file by default.
+//! It accepts `--tileset <path>` as well
+
+use {
+    crate::{
+        constants::*,
+        heuristics::*,
+        tiling::*,
+        tileset::TilesetDescription,
+        resource_loading::load_atlas_texture,
+        map_file::MapFile,
+        image_export::{save_png_with_thumbnail, surface_to_rgb, overlay_bgra},
+        noise::{NoiseParams, make_heightmap},
+        objects::{ObjectLayer, SpriteCache, render_objects},
+        scatter::{ScatterSettings, DEFAULT_SCATTER_RULES, scatter_objects},
+        CustomBitSet
+    },
+    rom_media_rs::image_rendering::{
+        bmp_sprite_decorators::TrueColorSurfaceSprite,
+        blittable::BlitBuilder
+    },
+    simple_tiled_wfc::{errors::WfcError, grid_generation::{WfcContextBuilder, WfcModule, WfcEntropyChoiceHeuristic}},
+    std::{
+        fs,
+        path::PathBuf,
+        sync::mpsc::channel
+    }
+};
+
+const MAX_COLLAPSE_ATTEMPTS: usize = 100;
+
+pub struct BatchOptions {
+    pub width: usize,
+    pub height: usize,
+    pub available_tiles: AvailableTiles,
+    pub weights: TerrainWeights,
+    pub heightmap: Option<NoiseParams>,
+    pub scatter: bool,
+    pub seed: u64,
+    pub count: usize,
+    pub out_dir: PathBuf,
+    /// Longer side of thumbnails, none are written without it
+    pub thumbnail: Option<usize>
+}
+
+pub struct RenderOptions {
+    pub map_path: PathBuf,
+    pub out_path: PathBuf,
+    pub thumbnail: Option<usize>
+}
+
+fn parse_terrains(names: &str, description: &TilesetDescription) -> Result<AvailableTiles, String> {
+    let mut mask = 0u16;
+    for name in names.split(',').map(|it| it.trim()).filter(|it| !it.is_empty()) {
+        mask |= 1 << description.terrain(name)?;
+    }
+    if mask == 0 {
+        return Err("--terrains expects at least one terrain".to_string());
+    }
+    Ok(AvailableTiles::from_mask(mask))
+}
+
+/// Parses `terrain=weight` pairs separated by commas, terrains not listed keep weight 1
+fn parse_weights(pairs: &str, description: &TilesetDescription) -> Result<TerrainWeights, String> {
+    let mut weights = TerrainWeights::new(description);
+    for pair in pairs.split(',').map(|it| it.trim()).filter(|it| !it.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        let name = parts.next().unwrap_or_default();
+        let terrain = description.terrain(name)?;
+        let weight = parts
+            .next()
+            .and_then(|it| it.parse::<f32>().ok())
+            .filter(|it| *it >= 0.0)
+            .ok_or_else(|| format!("--weights expects terrain=weight pairs, got {}", pair))?;
+        weights.terrains[terrain as usize] = weight;
+    }
+    Ok(weights)
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .as_deref()
+        .and_then(|it| it.parse().ok())
+        .ok_or_else(|| format!("{} expects a number", flag))
+}
+
+/// Returns `None` unless `--batch` is given
+pub fn batch_options_from_args(description: &TilesetDescription) -> Option<Result<BatchOptions, String>> {
+    if !std::env::args().any(|arg| arg == "--batch") {
+        return None;
+    }
+    let mut options = BatchOptions {
+        width: DEFAULT_MAP_WIDTH,
+        height: DEFAULT_MAP_HEIGHT,
+        available_tiles: AvailableTiles::default(),
+        weights: TerrainWeights::new(description),
+        heightmap: None,
+        scatter: false,
+        seed: DEFAULT_SEED,
+        count: 1,
+        out_dir: PathBuf::from("."),
+        thumbnail: None
+    };
+    let mut args = std::env::args().skip(1);
+    let result = (|| {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--batch" => {}
+                // read by `tileset_path_from_args`
+                "--tileset" => { args.next(); }
+                "--width" => options.width = parse_number(&arg, args.next())?,
+                "--height" => options.height = parse_number(&arg, args.next())?,
+                "--seed" => options.seed = parse_number(&arg, args.next())?,
+                "--count" => options.count = parse_number(&arg, args.next())?,
+                "--terrains" => options.available_tiles = parse_terrains(
+                    &args.next().ok_or_else(|| "--terrains expects a list of terrains".to_string())?,
+                    description
+                )?,
+                "--weights" => options.weights = parse_weights(
+                    &args.next().ok_or_else(|| "--weights expects terrain=weight pairs".to_string())?,
+                    description
+                )?,
+                "--heightmap" => options.heightmap = Some(NoiseParams::default()),
+                "--scatter" => options.scatter = true,
+                "--thumbnail" => options.thumbnail = Some(parse_number(&arg, args.next())?),
+                "--out" => options.out_dir = PathBuf::from(
+                    args.next().ok_or_else(|| "--out expects a directory".to_string())?
+                ),
+                _ => return Err(format!("unknown argument {}", arg))
+            }
+        }
+        let side_range = MIN_MAP_SIDE..=MAX_MAP_SIDE;
+        if !side_range.contains(&options.width) || !side_range.contains(&options.height) {
+            return Err(format!("map sides should be in {}..={}", MIN_MAP_SIDE, MAX_MAP_SIDE));
+        }
+        Ok(())
+    })();
+    Some(result.map(|_| options))
+}
+
+/// Returns `None` unless `--render` is given
+pub fn render_options_from_args() -> Option<Result<RenderOptions, String>> {
+    if !std::env::args().any(|arg| arg == "--render") {
+        return None;
+    }
+    let mut args = std::env::args().skip(1);
+    let mut map_path = None;
+    let mut out_path = None;
+    let mut thumbnail = None;
+    let result = (|| {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--render" => map_path = Some(PathBuf::from(
+                    args.next().ok_or_else(|| "--render expects a map file".to_string())?
+                )),
+                "--out" => out_path = Some(PathBuf::from(
+                    args.next().ok_or_else(|| "--out expects an image file".to_string())?
+                )),
+                "--thumbnail" => thumbnail = Some(parse_number(&arg, args.next())?),
+                "--tileset" => { args.next(); }
+                _ => return Err(format!("unknown argument {}", arg))
+            }
+        }
+        Ok(())
+    })();
+    Some(result.map(|_| {
+        let map_path = map_path.unwrap_or_default();
+        let out_path = out_path.unwrap_or_else(|| map_path.with_extension("png"));
+        RenderOptions { map_path, out_path, thumbnail }
+    }))
+}
+
+/// Builds the heuristic which picks a module for a slot. With `heightmap` given
+/// terrains follow a noise heightmap, otherwise modules are picked by their weights only
+pub fn make_choice_heuristic(
+    description: &TilesetDescription,
+    tiles: &[TileInfo],
+    width: usize,
+    height: usize,
+    tileset: CustomBitSet,
+    weights: &TerrainWeights,
+    heightmap: Option<&NoiseParams>,
+    seed: u64
+) -> Box<dyn WfcEntropyChoiceHeuristic<CustomBitSet> + Send> {
+    let tile_weights = weights.tile_weights(tiles);
+    match heightmap {
+        Some(params) => Box::new(HeightmapChoiceHeuristic {
+            rng: make_rng(seed, CHOICE_STREAM),
+            preferable_bits: tileset,
+            weights: tile_weights,
+            tile_bands: tiles
+                .iter()
+                .map(|it| {
+                    let band = |terrain: u8| description.terrains[terrain as usize].height;
+                    [band(it.north_west), band(it.north_east), band(it.south_west), band(it.south_east)]
+                })
+                .collect(),
+            heights: make_heightmap(width, height, params, seed),
+            params: params.clone()
+        }),
+        None => Box::new(WeightedChoiceHeuristic {
+            rng: make_rng(seed, CHOICE_STREAM),
+            preferable_bits: tileset,
+            weights: tile_weights
+        })
+    }
+}
+
+pub fn generate_tile_modules(
+    tiles: &[TileInfo],
+    modules: &[WfcModule<CustomBitSet>],
+    width: usize,
+    height: usize,
+    choice_heuristic: Box<dyn WfcEntropyChoiceHeuristic<CustomBitSet> + Send>,
+    seed: u64
+) -> Result<Vec<usize>, WfcError> {
+    let (transmitter, receiver) = channel();
+    let mut wfc_context = WfcContextBuilder::new(modules, width, height)
+        .with_entropy_heuristic(Box::new(
+            SeededEntropyHeuristic { rng: make_rng(seed, ENTROPY_STREAM) }
+        ))
+        .with_entropy_choice_heuristic(choice_heuristic)
+        .build();
+    wfc_context.collapse(MAX_COLLAPSE_ATTEMPTS, transmitter);
+    receiver.recv().unwrap().map(|mut tile_modules| {
+        canonicalize_tiles(tiles, &mut tile_modules);
+        tile_modules
+    })
+}
+
+/// Composites the map out of atlas tiles on the CPU, fill variants are picked by `seed`
+pub fn compose_tilemap(
+    atlas: &TrueColorSurfaceSprite,
+    tiles: &[TileInfo],
+    width: usize,
+    height: usize,
+    tile_modules: &[usize],
+    seed: u64
+) -> TrueColorSurfaceSprite {
+    let mut surface = TrueColorSurfaceSprite::new(width * 32, height * 32);
+    for (idx, tile_id) in resolve_variants(tiles, tile_modules, width, seed).into_iter().enumerate() {
+        let tile_info = &tiles[tile_id];
+        BlitBuilder::try_create(&mut surface, atlas)
+            .expect("failed to create blit builder")
+            .with_source_subrect(tile_info.tile_x, tile_info.tile_y, 32, 32)
+            .with_dest_pos((idx % width) as i32 * 32, (idx / width) as i32 * 32)
+            .blit();
+    }
+    surface
+}
+
+/// Composites the map with its objects into RGB triplets at 32 pixels per tile.
+/// Objects which failed to load are returned as errors and skipped
+pub fn render_map_rgb(
+    atlas: &TrueColorSurfaceSprite,
+    tiles: &[TileInfo],
+    map_file: &MapFile,
+    sprite_cache: &mut SpriteCache
+) -> (Vec<u8>, Vec<String>) {
+    let surface = compose_tilemap(
+        atlas,
+        tiles,
+        map_file.width,
+        map_file.height,
+        &map_file.tile_modules,
+        map_file.seed
+    );
+    let mut rgb = surface_to_rgb(&surface);
+    if map_file.objects.is_empty() {
+        return (rgb, Vec::new());
+    }
+    let object_layer = ObjectLayer { objects: map_file.objects.clone() };
+    let (objects, errors) = render_objects(&object_layer, sprite_cache, map_file.width, map_file.height);
+    overlay_bgra(&mut rgb, &objects);
+    (rgb, errors)
+}
+
+/// Generates all the maps and returns the amount of failed ones
+pub fn run_batch(options: &BatchOptions, description: &TilesetDescription) -> Result<usize, String> {
+    fs::create_dir_all(&options.out_dir)
+        .map_err(|e| format!("failed to create {}: {}", options.out_dir.display(), e))?;
+
+    let atlas = load_atlas_texture(description);
+    let tiles = make_tiling_lookup(description);
+    let modules = make_module_set(&tiles);
+    let tileset = options.available_tiles.make_bitset(&tiles);
+    let mut sprite_cache = SpriteCache::new();
+
+    let mut failed = 0;
+    for i in 0..options.count {
+        let seed = options.seed.wrapping_add(i as u64);
+        let tile_modules = match generate_tile_modules(
+            &tiles,
+            &modules,
+            options.width,
+            options.height,
+            make_choice_heuristic(
+                description,
+                &tiles,
+                options.width,
+                options.height,
+                tileset,
+                &options.weights,
+                options.heightmap.as_ref(),
+                seed
+            ),
+            seed
+        ) {
+            Ok(tile_modules) => tile_modules,
+            Err(e) => {
+                eprintln!("seed {}: generation failed: {:?}", seed, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let mut object_layer = ObjectLayer::new();
+        if options.scatter {
+            scatter_objects(
+                &mut object_layer,
+                &tiles,
+                description,
+                &tile_modules,
+                options.width,
+                options.height,
+                DEFAULT_SCATTER_RULES,
+                &ScatterSettings::default(),
+                seed
+            );
+        }
+
+        let map_path = options.out_dir.join(format!("map_{}.orommap", seed));
+        let map_file = MapFile {
+            width: options.width,
+            height: options.height,
+            tileset_version: description.version,
+            available_tiles: options.available_tiles.clone(),
+            seed,
+            tile_modules,
+            objects: object_layer.objects.clone()
+        };
+        if let Err(e) = map_file.save(&map_path) {
+            eprintln!("failed to save {}: {}", map_path.display(), e);
+            failed += 1;
+            continue;
+        }
+
+        let preview_path = options.out_dir.join(format!("map_{}.png", seed));
+        let (rgb, errors) = render_map_rgb(&atlas, &tiles, &map_file, &mut sprite_cache);
+        for e in errors {
+            eprintln!("seed {}: {}", seed, e);
+        }
+        let (width, height) = (options.width * 32, options.height * 32);
+        if let Err(e) = save_png_with_thumbnail(&preview_path, width, height, &rgb, options.thumbnail) {
+            eprintln!("failed to save {}: {}", preview_path.display(), e);
+            failed += 1;
+            continue;
+        }
+
+        println!("{}/{}: {}", i + 1, options.count, map_path.display());
+    }
+    Ok(failed)
+}
+
+/// Renders a saved map into a PNG
+pub fn run_render(options: &RenderOptions, description: &TilesetDescription) -> Result<(), String> {
+    let map_file = MapFile::load(&options.map_path, description.version)
+        .map_err(|e| format!("failed to load {}: {}", options.map_path.display(), e))?;
+    let tiles = make_tiling_lookup(description);
+    if map_file.tile_modules.iter().any(|&tile_id| tile_id >= tiles.len()) {
+        return Err(format!("failed to load {}: unknown tile index", options.map_path.display()));
+    }
+    let (rgb, errors) = render_map_rgb(&load_atlas_texture(description), &tiles, &map_file, &mut SpriteCache::new());
+    for e in errors {
+        eprintln!("{}", e);
+    }
+    let (width, height) = (map_file.width * 32, map_file.height * 32);
+    save_png_with_thumbnail(&options.out_path, width, height, &rgb, options.thumbnail)
+        .map_err(|e| format!("failed to save {}: {}", options.out_path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::tileset::tests::stock_tileset};
+
+    fn generate(description: &TilesetDescription, seed: u64) -> Vec<usize> {
+        let (width, height) = (MIN_MAP_SIDE + 4, MIN_MAP_SIDE);
+        let tiles = make_tiling_lookup(description);
+        let modules = make_module_set(&tiles);
+        let choice_heuristic = make_choice_heuristic(
+            description,
+            &tiles,
+            width,
+            height,
+            AvailableTiles::default().make_bitset(&tiles),
+            &TerrainWeights::new(description),
+            None,
+            seed
+        );
+        generate_tile_modules(&tiles, &modules, width, height, choice_heuristic, seed).unwrap()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_map() {
+        let description = stock_tileset();
+        assert_eq!(generate(&description, 42), generate(&description, 42));
+        assert_ne!(generate(&description, 42), generate(&description, 43));
+    }
+}
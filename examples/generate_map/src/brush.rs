@@ -0,0 +1,187 @@
+use {
+    crate::tiling::TileInfo,
+    std::collections::VecDeque
+};
+
+pub const MAX_BRUSH_RADIUS: usize = 8;
+/// Flood fill stops growing after this many cells, every one of them costs a local collapse
+pub const MAX_FILL_CELLS: usize = 4096;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum BrushShape {
+    Square,
+    Circle
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum PaintMode {
+    Brush,
+    Rectangle,
+    FloodFill,
+    /// Waypoints which get joined by a continuous road or river
+    Path,
+    /// Places and removes objects instead of painting terrain
+    Objects
+}
+
+pub struct Brush {
+    pub shape: BrushShape,
+    pub radius: usize
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Brush {
+    pub fn new() -> Self {
+        Self { shape: BrushShape::Square, radius: 0 }
+    }
+
+    /// Cells (row, column) covered by the brush, sorted from the center outwards
+    pub fn footprint(&self, row: usize, column: usize, width: usize, height: usize) -> Vec<(usize, usize)> {
+        let radius = self.radius as i64;
+        let mut cells = Vec::new();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if self.shape == BrushShape::Circle && dx * dx + dy * dy > radius * (radius + 1) {
+                    continue;
+                }
+                let (cell_row, cell_column) = (row as i64 + dy, column as i64 + dx);
+                if cell_row < 0 || cell_column < 0 || cell_row >= height as i64 || cell_column >= width as i64 {
+                    continue;
+                }
+                cells.push((cell_row as usize, cell_column as usize));
+            }
+        }
+        cells.sort_by_key(|&(cell_row, cell_column)| {
+            let (dy, dx) = (cell_row as i64 - row as i64, cell_column as i64 - column as i64);
+            dx * dx + dy * dy
+        });
+        cells
+    }
+}
+
+/// Cells (row, column) of a rectangle given by two opposite corners in (column, row) form
+pub fn rectangle_cells(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let (min_column, max_column) = (from.0.min(to.0), from.0.max(to.0));
+    let (min_row, max_row) = (from.1.min(to.1), from.1.max(to.1));
+    let mut cells = Vec::with_capacity((max_row - min_row + 1) * (max_column - min_column + 1));
+    for row in min_row..=max_row {
+        for column in min_column..=max_column {
+            cells.push((row, column));
+        }
+    }
+    cells
+}
+
+/// Collects the 4-connected region of tiles sharing the dominant terrain of the starting one.
+/// Returns the cells (row, column) and whether the region was cut by `MAX_FILL_CELLS`
+pub fn flood_fill_region(
+    tiles: &[TileInfo],
+    tile_modules: &[usize],
+    width: usize,
+    height: usize,
+    start: (usize, usize)
+) -> (Vec<(usize, usize)>, bool) {
+    let terrain = tiles[tile_modules[start.0 * width + start.1]].dominant_terrain();
+    let mut visited = vec![false; width * height];
+    let mut queue = VecDeque::new();
+    let mut cells = Vec::new();
+    visited[start.0 * width + start.1] = true;
+    queue.push_back(start);
+    while let Some((row, column)) = queue.pop_front() {
+        if cells.len() == MAX_FILL_CELLS {
+            return (cells, true);
+        }
+        cells.push((row, column));
+        let neighbours = [
+            (row.wrapping_sub(1), column),
+            (row + 1, column),
+            (row, column.wrapping_sub(1)),
+            (row, column + 1)
+        ];
+        for &(next_row, next_column) in &neighbours {
+            if next_row >= height || next_column >= width { continue; }
+            let idx = next_row * width + next_column;
+            if visited[idx] || tiles[tile_modules[idx]].dominant_terrain() != terrain { continue; }
+            visited[idx] = true;
+            queue.push_back((next_row, next_column));
+        }
+    }
+    (cells, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{tiling::{make_tiling_lookup, fill_tile}, tileset::tests::stock_tileset}
+    };
+
+    #[test]
+    fn footprint_is_clipped_at_map_edges() {
+        let brush = Brush { shape: BrushShape::Square, radius: 2 };
+        let (width, height) = (10, 8);
+        assert_eq!(brush.footprint(4, 4, width, height).len(), 25);
+
+        let corner = brush.footprint(0, 0, width, height);
+        assert_eq!(corner.len(), 9);
+        assert_eq!(corner[0], (0, 0));
+        assert!(corner.iter().all(|&(row, column)| row <= 2 && column <= 2));
+
+        let far_corner = brush.footprint(height - 1, width - 1, width, height);
+        assert_eq!(far_corner.len(), 9);
+        assert!(far_corner.iter().all(|&(row, column)| row < height && column < width));
+    }
+
+    #[test]
+    fn circle_footprint_drops_the_corners() {
+        let brush = Brush { shape: BrushShape::Circle, radius: 2 };
+        let cells = brush.footprint(5, 5, 20, 20);
+        assert_eq!(cells.len(), 21);
+        assert!(!cells.contains(&(3, 3)));
+        assert!(cells.contains(&(3, 5)));
+    }
+
+    #[test]
+    fn rectangle_covers_both_corners_in_any_order() {
+        let cells = rectangle_cells((4, 1), (2, 3));
+        assert_eq!(cells.len(), 9);
+        assert_eq!(cells.first(), Some(&(1, 2)));
+        assert_eq!(cells.last(), Some(&(3, 4)));
+    }
+
+    #[test]
+    fn flood_fill_stops_at_other_terrains() {
+        let description = stock_tileset();
+        let tiles = make_tiling_lookup(&description);
+        let (land, water) = (description.terrain("land").unwrap(), description.terrain("water").unwrap());
+        let (width, height) = (5, 4);
+        // a water column splits the map into two land regions
+        let tile_modules: Vec<usize> = (0..width * height)
+            .map(|idx| fill_tile(&tiles, if idx % width == 2 { water } else { land }))
+            .collect();
+
+        let (cells, cut) = flood_fill_region(&tiles, &tile_modules, width, height, (1, 0));
+        assert!(!cut);
+        assert_eq!(cells.len(), 8);
+        assert!(cells.iter().all(|&(_, column)| column < 2));
+
+        let (cells, _) = flood_fill_region(&tiles, &tile_modules, width, height, (3, 2));
+        assert_eq!(cells.len(), 4);
+    }
+
+    #[test]
+    fn flood_fill_is_cut_at_the_cell_limit() {
+        let description = stock_tileset();
+        let tiles = make_tiling_lookup(&description);
+        let side = 70;
+        let tile_modules = vec![fill_tile(&tiles, 0); side * side];
+        let (cells, cut) = flood_fill_region(&tiles, &tile_modules, side, side, (side / 2, side / 2));
+        assert!(cut);
+        assert_eq!(cells.len(), MAX_FILL_CELLS);
+    }
+}
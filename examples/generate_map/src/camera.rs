@@ -0,0 +1,89 @@
+use crate::constants::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+pub const MIN_ZOOM: f32 = 0.25;
+pub const MAX_ZOOM: f32 = 4.0;
+pub const ZOOM_STEP: f32 = 1.15;
+
+/// View over the tilemap. Position is the top left corner of the view measured in tiles
+pub struct Camera {
+    pub x: f32,
+    pub y: f32,
+    pub zoom: f32
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Self { x: 0.0, y: 0.0, zoom: 1.0 }
+    }
+
+    /// Amount of tiles which fit into the window along each axis
+    pub fn visible_tiles(&self) -> (f32, f32) {
+        (
+            SCREEN_WIDTH as f32 / 32.0 / self.zoom,
+            SCREEN_HEIGHT as f32 / 32.0 / self.zoom
+        )
+    }
+
+    pub fn screen_to_tile(&self, x: f32, y: f32, screen_size: (f32, f32)) -> (f32, f32) {
+        let (visible_w, visible_h) = self.visible_tiles();
+        (
+            self.x + x / screen_size.0 * visible_w,
+            self.y + y / screen_size.1 * visible_h
+        )
+    }
+
+    pub fn pan_by_pixels(&mut self, dx: f32, dy: f32, screen_size: (f32, f32)) {
+        let (visible_w, visible_h) = self.visible_tiles();
+        self.x += dx / screen_size.0 * visible_w;
+        self.y += dy / screen_size.1 * visible_h;
+    }
+
+    pub fn pan_by_tiles(&mut self, dx: f32, dy: f32) {
+        self.x += dx;
+        self.y += dy;
+    }
+
+    /// Zooms keeping the tile under the cursor in place
+    pub fn zoom_at(&mut self, factor: f32, x: f32, y: f32, screen_size: (f32, f32)) {
+        let (anchor_x, anchor_y) = self.screen_to_tile(x, y, screen_size);
+        self.zoom = (self.zoom * factor).max(MIN_ZOOM).min(MAX_ZOOM);
+        let (visible_w, visible_h) = self.visible_tiles();
+        self.x = anchor_x - x / screen_size.0 * visible_w;
+        self.y = anchor_y - y / screen_size.1 * visible_h;
+    }
+
+    pub fn center_on(&mut self, tile_x: f32, tile_y: f32) {
+        let (visible_w, visible_h) = self.visible_tiles();
+        self.x = tile_x - visible_w / 2.0;
+        self.y = tile_y - visible_h / 2.0;
+    }
+
+    /// Keeps the map on screen. A map smaller than the view is centered
+    pub fn clamp_to_map(&mut self, map_width: usize, map_height: usize) {
+        let (visible_w, visible_h) = self.visible_tiles();
+        let clamp_axis = |position: f32, visible: f32, map_side: f32| {
+            if visible >= map_side {
+                (map_side - visible) / 2.0
+            } else {
+                position.max(0.0).min(map_side - visible)
+            }
+        };
+        self.x = clamp_axis(self.x, visible_w, map_width as f32);
+        self.y = clamp_axis(self.y, visible_h, map_height as f32);
+    }
+
+    /// Offset and scale which turn screen quad uv into uv of the tilemap texture
+    pub fn uv_transform(&self, map_width: usize, map_height: usize) -> ((f32, f32), (f32, f32)) {
+        let (visible_w, visible_h) = self.visible_tiles();
+        (
+            (self.x / map_width as f32, self.y / map_height as f32),
+            (visible_w / map_width as f32, visible_h / map_height as f32)
+        )
+    }
+}
@@ -1,51 +1,41 @@
 pub const GRAPHICS_RES: &[u8] = include_bytes!("../../assets/GRAPHICS.RES");
-pub const GUI_TEXTURE_BYTES: &[&[u8]] = &[
-    include_bytes!("../../assets/land.bmp"),
-    include_bytes!("../../assets/grass.bmp"),
-    include_bytes!("../../assets/plateau.bmp"),
-    include_bytes!("../../assets/sand.bmp"),
-    include_bytes!("../../assets/savannah.bmp"),
-    include_bytes!("../../assets/rocks.bmp"),
-    include_bytes!("../../assets/highrock.bmp"),
-    include_bytes!("../../assets/water.bmp"),
-    include_bytes!("../../assets/road.bmp")
+/// Icons of the stock terrains by their names, other terrains are shown with their color
+pub const TERRAIN_ICONS: &[(&str, &[u8])] = &[
+    ("land", include_bytes!("../../assets/land.bmp")),
+    ("grass", include_bytes!("../../assets/grass.bmp")),
+    ("plateau", include_bytes!("../../assets/plateau.bmp")),
+    ("sand", include_bytes!("../../assets/sand.bmp")),
+    ("savannah", include_bytes!("../../assets/savannah.bmp")),
+    ("rocks", include_bytes!("../../assets/rocks.bmp")),
+    ("high_rocks", include_bytes!("../../assets/highrock.bmp")),
+    ("water", include_bytes!("../../assets/water.bmp")),
+    ("road", include_bytes!("../../assets/road.bmp"))
 ];
 pub const INFO_TEXT_BYTES: &[u8] = include_bytes!("../../assets/info_text.bmp");
 pub const JETBRAINS_MONO_FONT: &[u8] = include_bytes!("../../assets/JetBrainsMono-Medium.ttf");
 
-pub const LAND: u8 = 0;
-pub const GRASS: u8 = 1;
-pub const PLATEAU: u8 = 2;
-pub const SAND: u8 = 3;
-pub const SAVANNAH: u8 = 4;
-pub const ROCKS: u8 = 5;
-pub const HIGH_ROCKS: u8 = 6;
-pub const WATER: u8 = 7;
-pub const ROAD: u8 = 8;
-
-pub const fn get_tool_color(tool: u8) -> (f32, f32, f32) {
-    match tool {
-        LAND => (0.5, 0.4, 0.22),
-        GRASS => (0.0, 0.7, 0.3),
-        PLATEAU => (0.44, 0.4, 0.52),
-        SAND => (0.7, 0.7, 0.0),
-        SAVANNAH => (0.0, 0.7, 0.7),
-        ROCKS => (0.7, 0.6, 0.0),
-        HIGH_ROCKS => (1.0, 1.0, 1.0),
-        WATER => (0.4, 0.45, 0.8),
-        ROAD => (0.65, 0.6, 0.6),
-        _ => (0.0, 0.4, 0.7)
-    }
-}
+/// The first terrain of the tileset, new maps are filled with it
+pub const BASE_TERRAIN: u8 = 0;
 
 pub const SCREEN_WIDTH: i32 = 1280;
 pub const SCREEN_HEIGHT: i32 = 800;
 
 pub const MULTIPLIER: usize = 1;
-pub const WIDTH: usize = 40 * MULTIPLIER;
-pub const HEIGHT: usize = 25 * MULTIPLIER;
-pub const STAGE_SURFACE_W: usize = SCREEN_WIDTH as usize * MULTIPLIER;
-pub const STAGE_SURFACE_H: usize = SCREEN_HEIGHT as usize * MULTIPLIER;
+pub const DEFAULT_MAP_WIDTH: usize = 40 * MULTIPLIER;
+pub const DEFAULT_MAP_HEIGHT: usize = 25 * MULTIPLIER;
+pub const MIN_MAP_SIDE: usize = 8;
+/// The whole map is blitted into one stage surface at 32x32 pixels per tile and uploaded as
+/// a single RGBA texture. 128 tiles make it 4096x4096, the largest texture size which GL ES 2
+/// and WebGL implementations commonly guarantee, and 64 MB of surface memory on the CPU
+/// side. Larger maps need the surface split into several textures first
+pub const MAX_MAP_SIDE: usize = 128;
+pub const DEFAULT_SEED: u64 = 0;
+pub const MAX_DRAWS_PER_UPDATE: usize = 32;
+pub const MAX_REPAIR_ATTEMPTS: usize = 8;
+/// Longer side of map thumbnails exported from the editor, in pixels
+pub const THUMBNAIL_SIZE: usize = 256;
+/// Where `cargo run` started from `examples/generate_map` finds the tileset
+pub const DEFAULT_TILESET_PATH: &str = "../assets/tileset.ron";
 
 #[repr(C)]
 pub struct Vec2 {
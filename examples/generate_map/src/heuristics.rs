@@ -4,15 +4,72 @@ use {
         grid_generation::{
             WfcEntropyHeuristic,
             WfcModule,
-            WfcEntropyChoiceHeuristic,
-            DefaultEntropyChoiceHeuristic
+            WfcEntropyChoiceHeuristic
         }
     },
     bitsetium::{BitSearch, BitEmpty, BitSet, BitIntersection, BitUnion, BitTestNone},
-    rand::{thread_rng, Rng},
-    std::hash::Hash
+    crate::noise::{HeightBand, NoiseParams},
+    rand::{Rng, SeedableRng, rngs::StdRng},
+    std::{hash::Hash, cell::RefCell}
 };
 
+/// Salts which split one seed into independent streams. Seeded the same way, the cell order
+/// and the tile choice would draw the same numbers and correlate with each other
+pub const ENTROPY_STREAM: u64 = 0x5851_F42D_4C95_7F2D;
+pub const CHOICE_STREAM: u64 = 0x1405_7B7E_F767_814F;
+
+pub fn make_rng(seed: u64, stream: u64) -> RefCell<StdRng> {
+    RefCell::new(StdRng::seed_from_u64(seed ^ stream))
+}
+
+/// Mixes a seed with a position, so local collapses get their own reproducible streams
+pub fn local_seed(seed: u64, row: usize, column: usize) -> u64 {
+    seed ^ ((row as u64) << 32 | column as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+fn choose_random_bit<TBitSet>(rng: &RefCell<StdRng>, bits: &TBitSet) -> Option<usize>
+    where TBitSet:
+    BitSearch + BitEmpty + BitSet + BitIntersection +
+    BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
+    BitUnion<Output = TBitSet>
+{
+    let bits_count = get_bits_set_count(bits);
+    if bits_count == 0 {
+        return None;
+    }
+    let random_bit_id = rng.borrow_mut().gen_range(0, bits_count);
+    let mut iterator = BitsIterator::new(bits);
+    iterator.nth(random_bit_id)
+}
+
+/// Picks a bit with probability proportional to its weight.
+/// Falls back to a uniform choice when all the candidates weigh nothing
+fn choose_weighted_bit<TBitSet>(rng: &RefCell<StdRng>, bits: &TBitSet, weights: &[f32]) -> Option<usize>
+    where TBitSet:
+    BitSearch + BitEmpty + BitSet + BitIntersection +
+    BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
+    BitUnion<Output = TBitSet>
+{
+    let weight_of = |bit: usize| weights.get(bit).copied().unwrap_or(0.0).max(0.0);
+    let total: f32 = BitsIterator::new(bits).map(weight_of).sum();
+    if total <= 0.0 {
+        return choose_random_bit(rng, bits);
+    }
+    let mut threshold = rng.borrow_mut().gen_range(0.0, total);
+    let mut last = None;
+    for bit in BitsIterator::new(bits) {
+        let weight = weight_of(bit);
+        if weight <= 0.0 { continue; }
+        if threshold < weight {
+            return Some(bit);
+        }
+        threshold -= weight;
+        last = Some(bit);
+    }
+    // rounding errors may leave a tiny remainder
+    last
+}
+
 pub fn square_dist(x1: usize, y1: usize, x2: usize, y2: usize) -> f64 {
     (x2 as f64 - x1 as f64) * (x2 as f64 - x1 as f64) +
     (y2 as f64 - y1 as f64) * (y2 as f64 - y1 as f64)
@@ -51,13 +108,36 @@ impl<TBitSet> WfcEntropyHeuristic<TBitSet> for LeastDistanceHeuristic
     }
 }
 
+/// Picks one of the least entropy slots at random using an explicitly seeded generator
+pub struct SeededEntropyHeuristic {
+    pub rng: RefCell<StdRng>
+}
+
+impl<TBitSet> WfcEntropyHeuristic<TBitSet> for SeededEntropyHeuristic
+    where TBitSet:
+    BitSearch + BitEmpty + BitSet + BitIntersection +
+    BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
+    BitUnion<Output = TBitSet>
+{
+    fn choose_next_collapsed_slot(
+        &self,
+        _width: usize,
+        _height: usize,
+        _modules: &[WfcModule<TBitSet>],
+        available_indices: &[usize]
+    ) -> usize {
+        self.rng.borrow_mut().gen_range(0, available_indices.len())
+    }
+}
+
+/// Picks among the preferable modules left in a slot when there are any, otherwise among all of them
 pub struct DrawingChoiceHeuristic<TBitSet>
     where TBitSet:
     BitSearch + BitEmpty + BitSet + BitIntersection +
     BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
     BitUnion<Output = TBitSet>
 {
-    pub fallback: DefaultEntropyChoiceHeuristic,
+    pub rng: RefCell<StdRng>,
     pub preferable_bits: TBitSet
 }
 impl<TBitSet> WfcEntropyChoiceHeuristic<TBitSet> for DrawingChoiceHeuristic<TBitSet>
@@ -68,34 +148,35 @@ impl<TBitSet> WfcEntropyChoiceHeuristic<TBitSet> for DrawingChoiceHeuristic<TBit
 {
     fn choose_least_entropy_bit(
         &self,
-        width: usize,
-        height: usize,
-        row: usize,
-        column: usize,
-        modules: &[WfcModule<TBitSet>],
+        _width: usize,
+        _height: usize,
+        _row: usize,
+        _column: usize,
+        _modules: &[WfcModule<TBitSet>],
         slot_bits: &TBitSet
     ) -> Option<usize> {
         let intersection = self.preferable_bits.intersection(*slot_bits);
         if get_bits_set_count(&intersection) > 0 {
-            let mut rng = thread_rng();
-            let random_bit_id = rng.gen_range(0, get_bits_set_count(&intersection));
-            let mut iterator = BitsIterator::new(&intersection);
-            Some(iterator.nth(random_bit_id).unwrap())
+            choose_random_bit(&self.rng, &intersection)
         } else {
-            self.fallback.choose_least_entropy_bit(width, height, row, column, modules, slot_bits)
+            choose_random_bit(&self.rng, slot_bits)
         }
     }
 }
 
-pub struct StrictDrawingChoiceHeuristic<TBitSet>
+/// Picks one of the preferable modules left in a slot with probability proportional to its weight,
+/// or one of all the modules left when none of them is preferable
+pub struct WeightedChoiceHeuristic<TBitSet>
     where TBitSet:
     BitSearch + BitEmpty + BitSet + BitIntersection +
     BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
     BitUnion<Output = TBitSet>
 {
-    pub preferable_bits: TBitSet
+    pub rng: RefCell<StdRng>,
+    pub preferable_bits: TBitSet,
+    pub weights: Vec<f32>
 }
-impl<TBitSet> WfcEntropyChoiceHeuristic<TBitSet> for StrictDrawingChoiceHeuristic<TBitSet>
+impl<TBitSet> WfcEntropyChoiceHeuristic<TBitSet> for WeightedChoiceHeuristic<TBitSet>
     where TBitSet:
     BitSearch + BitEmpty + BitSet + BitIntersection +
     BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
@@ -112,12 +193,137 @@ impl<TBitSet> WfcEntropyChoiceHeuristic<TBitSet> for StrictDrawingChoiceHeuristi
     ) -> Option<usize> {
         let intersection = self.preferable_bits.intersection(*slot_bits);
         if get_bits_set_count(&intersection) > 0 {
-            let mut rng = thread_rng();
-            let random_bit_id = rng.gen_range(0, get_bits_set_count(&intersection));
-            let mut iterator = BitsIterator::new(&intersection);
-            Some(iterator.nth(random_bit_id).unwrap())
+            choose_weighted_bit(&self.rng, &intersection, &self.weights)
         } else {
-            None
+            choose_weighted_bit(&self.rng, slot_bits, &self.weights)
+        }
+    }
+}
+
+/// Prefers modules whose corner terrains look natural in the band of a heightmap at the slot,
+/// see `TerrainDescription::height`. Among the best matching modules the choice is weighted
+pub struct HeightmapChoiceHeuristic<TBitSet>
+    where TBitSet:
+    BitSearch + BitEmpty + BitSet + BitIntersection +
+    BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
+    BitUnion<Output = TBitSet>
+{
+    pub rng: RefCell<StdRng>,
+    pub preferable_bits: TBitSet,
+    pub weights: Vec<f32>,
+    /// Bands of the terrains in the corners of every module: north west, north east,
+    /// south west, south east
+    pub tile_bands: Vec<[Option<HeightBand>; 4]>,
+    pub heights: Vec<f32>,
+    pub params: NoiseParams
+}
+impl<TBitSet> WfcEntropyChoiceHeuristic<TBitSet> for HeightmapChoiceHeuristic<TBitSet>
+    where TBitSet:
+    BitSearch + BitEmpty + BitSet + BitIntersection +
+    BitUnion + BitTestNone + Hash + Eq + Copy + BitIntersection<Output = TBitSet> +
+    BitUnion<Output = TBitSet>
+{
+    fn choose_least_entropy_bit(
+        &self,
+        width: usize,
+        _height: usize,
+        row: usize,
+        column: usize,
+        _modules: &[WfcModule<TBitSet>],
+        slot_bits: &TBitSet
+    ) -> Option<usize> {
+        let mut intersection = self.preferable_bits.intersection(*slot_bits);
+        if get_bits_set_count(&intersection) == 0 {
+            intersection = *slot_bits;
+        }
+        let height = self.heights.get(row * width + column).copied().unwrap_or(0.5);
+        let suggested = Some(self.params.band_at(height));
+        let score = |bit: usize| self.tile_bands
+            .get(bit)
+            .map(|bands| bands.iter().filter(|&&band| band == suggested).count())
+            .unwrap_or(0);
+        let best_score = match BitsIterator::new(&intersection).map(score).max() {
+            Some(best_score) => best_score,
+            None => return None
+        };
+        let mut best_bits = TBitSet::empty();
+        for bit in BitsIterator::new(&intersection).filter(|&bit| score(bit) == best_score) {
+            best_bits.set(bit);
+        }
+        choose_weighted_bit(&self.rng, &best_bits, &self.weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::CustomBitSet};
+
+    fn bits(ids: &[usize]) -> CustomBitSet {
+        let mut bits = CustomBitSet::empty();
+        for &id in ids {
+            bits.set(id);
+        }
+        bits
+    }
+
+    fn count_choices(bits: &CustomBitSet, weights: &[f32], draws: usize) -> Vec<usize> {
+        let rng = make_rng(7, CHOICE_STREAM);
+        let mut counts = vec![0; weights.len().max(8)];
+        for _ in 0..draws {
+            counts[choose_weighted_bit(&rng, bits, weights).unwrap()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn choice_follows_the_weights() {
+        let counts = count_choices(&bits(&[1, 2, 3]), &[5.0, 0.0, 1.0, 3.0], 4000);
+        assert_eq!(counts[0], 0, "bit outside the set was chosen");
+        assert_eq!(counts[1], 0, "weightless bit was chosen");
+        let ratio = counts[3] as f32 / counts[2] as f32;
+        assert!(ratio > 2.5 && ratio < 3.5, "expected about 3 to 1, got {}", ratio);
+    }
+
+    #[test]
+    fn weightless_candidates_are_chosen_uniformly() {
+        let counts = count_choices(&bits(&[0, 4]), &[0.0; 8], 2000);
+        assert_eq!(counts[0] + counts[4], 2000);
+        assert!(counts[0] > 800 && counts[4] > 800, "got {:?}", counts);
+    }
+
+    #[test]
+    fn empty_set_gives_no_choice() {
+        let rng = make_rng(7, CHOICE_STREAM);
+        assert_eq!(choose_weighted_bit(&rng, &CustomBitSet::empty(), &[1.0; 4]), None);
+    }
+
+    #[test]
+    fn drawing_falls_back_to_the_slot_without_preferred_bits() {
+        let heuristic = DrawingChoiceHeuristic {
+            rng: make_rng(7, CHOICE_STREAM),
+            preferable_bits: bits(&[5, 6])
+        };
+        let slot_bits = bits(&[1, 2]);
+        for _ in 0..50 {
+            let bit = heuristic.choose_least_entropy_bit(4, 4, 0, 0, &[], &slot_bits);
+            assert!(bit == Some(1) || bit == Some(2), "got {:?}", bit);
+        }
+        let slot_bits = bits(&[1, 2, 6]);
+        for _ in 0..50 {
+            assert_eq!(heuristic.choose_least_entropy_bit(4, 4, 0, 0, &[], &slot_bits), Some(6));
+        }
+    }
+
+    #[test]
+    fn weighted_falls_back_to_the_slot_without_preferred_bits() {
+        let heuristic = WeightedChoiceHeuristic {
+            rng: make_rng(7, CHOICE_STREAM),
+            preferable_bits: bits(&[5, 6]),
+            weights: vec![1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0]
+        };
+        let slot_bits = bits(&[1, 2]);
+        for _ in 0..50 {
+            assert_eq!(heuristic.choose_least_entropy_bit(4, 4, 0, 0, &[], &slot_bits), Some(1));
         }
     }
-}
\ No newline at end of file
+}
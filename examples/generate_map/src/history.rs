@@ -0,0 +1,205 @@
+const MAX_HISTORY_LENGTH: usize = 256;
+
+pub struct TileChange {
+    pub idx: usize,
+    pub before: usize,
+    pub after: usize
+}
+
+/// Undo/redo stacks over `tile_modules`. Every step keeps only the tiles it has changed.
+/// Changes recorded between `begin_stroke` and `end_stroke` are merged into a single step
+pub struct History {
+    undo_stack: Vec<Vec<TileChange>>,
+    redo_stack: Vec<Vec<TileChange>>,
+    stroke: Option<Vec<TileChange>>
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            stroke: None
+        }
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo_stack.len()
+    }
+
+    pub fn begin_stroke(&mut self) {
+        self.end_stroke();
+        self.stroke = Some(Vec::new());
+    }
+
+    pub fn end_stroke(&mut self) {
+        if let Some(changes) = self.stroke.take() {
+            self.push(changes);
+        }
+    }
+
+    pub fn record(&mut self, before: &[usize], after: &[usize]) {
+        if before.len() != after.len() {
+            // dimensions have changed, so previous steps are meaningless now
+            self.clear();
+            return;
+        }
+        let changes: Vec<TileChange> = before
+            .iter()
+            .zip(after.iter())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(idx, (&before, &after))| TileChange { idx, before, after })
+            .collect();
+        match &mut self.stroke {
+            Some(stroke) => stroke.extend(changes),
+            None => self.push(changes)
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.stroke = None;
+    }
+
+    /// Reverts the last step and returns indices of the tiles it has touched
+    pub fn undo(&mut self, tile_modules: &mut [usize]) -> Vec<usize> {
+        self.end_stroke();
+        match self.undo_stack.pop() {
+            Some(changes) => {
+                // a stroke may touch the same tile several times, so go backwards
+                for change in changes.iter().rev() {
+                    tile_modules[change.idx] = change.before;
+                }
+                let touched = changes.iter().map(|it| it.idx).collect();
+                self.redo_stack.push(changes);
+                touched
+            }
+            None => Vec::new()
+        }
+    }
+
+    /// Reapplies the last reverted step and returns indices of the tiles it has touched
+    pub fn redo(&mut self, tile_modules: &mut [usize]) -> Vec<usize> {
+        self.end_stroke();
+        match self.redo_stack.pop() {
+            Some(changes) => {
+                for change in changes.iter() {
+                    tile_modules[change.idx] = change.after;
+                }
+                let touched = changes.iter().map(|it| it.idx).collect();
+                self.undo_stack.push(changes);
+                touched
+            }
+            None => Vec::new()
+        }
+    }
+
+    fn push(&mut self, changes: Vec<TileChange>) {
+        if changes.is_empty() { return; }
+        self.redo_stack.clear();
+        self.undo_stack.push(changes);
+        if self.undo_stack.len() > MAX_HISTORY_LENGTH {
+            self.undo_stack.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_tiles_are_recorded() {
+        let mut history = History::new();
+        history.record(&[1, 2, 3], &[1, 5, 3]);
+        history.record(&[1, 5, 3], &[1, 5, 3]);
+        assert_eq!(history.undo_len(), 1);
+        let mut tile_modules = vec![1, 5, 3];
+        assert_eq!(history.undo(&mut tile_modules), vec![1]);
+        assert_eq!(tile_modules, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_tiles() {
+        let mut history = History::new();
+        let mut tile_modules = vec![0, 0, 0, 0];
+        for (idx, tile) in [(0, 4), (3, 7)].iter() {
+            let before = tile_modules.clone();
+            tile_modules[*idx] = *tile;
+            history.record(&before, &tile_modules);
+        }
+        assert_eq!(history.undo_len(), 2);
+
+        history.undo(&mut tile_modules);
+        assert_eq!(tile_modules, vec![4, 0, 0, 0]);
+        history.undo(&mut tile_modules);
+        assert_eq!(tile_modules, vec![0, 0, 0, 0]);
+        assert!(history.undo(&mut tile_modules).is_empty());
+        assert_eq!(history.redo_len(), 2);
+
+        history.redo(&mut tile_modules);
+        history.redo(&mut tile_modules);
+        assert_eq!(tile_modules, vec![4, 0, 0, 7]);
+        assert!(history.redo(&mut tile_modules).is_empty());
+    }
+
+    #[test]
+    fn new_record_drops_redo_steps() {
+        let mut history = History::new();
+        let mut tile_modules = vec![0, 0];
+        history.record(&[0, 0], &[1, 0]);
+        tile_modules[0] = 1;
+        history.undo(&mut tile_modules);
+        assert_eq!(history.redo_len(), 1);
+
+        history.record(&[0, 0], &[0, 2]);
+        assert_eq!(history.redo_len(), 0);
+        assert_eq!(history.undo_len(), 1);
+    }
+
+    #[test]
+    fn stroke_is_undone_as_one_step() {
+        let mut history = History::new();
+        let mut tile_modules = vec![0, 0, 0];
+        history.begin_stroke();
+        history.record(&[0, 0, 0], &[1, 0, 0]);
+        history.record(&[1, 0, 0], &[2, 0, 0]);
+        history.record(&[2, 0, 0], &[2, 3, 0]);
+        history.end_stroke();
+        assert_eq!(history.undo_len(), 1);
+
+        tile_modules.copy_from_slice(&[2, 3, 0]);
+        history.undo(&mut tile_modules);
+        // the first tile was touched twice, so the oldest value should come back
+        assert_eq!(tile_modules, vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn resize_clears_the_history() {
+        let mut history = History::new();
+        history.record(&[0, 0], &[1, 0]);
+        history.record(&[1, 0], &[1, 0, 0, 0]);
+        assert_eq!((history.undo_len(), history.redo_len()), (0, 0));
+    }
+
+    #[test]
+    fn history_length_is_capped() {
+        let mut history = History::new();
+        for step in 0..MAX_HISTORY_LENGTH + 10 {
+            history.record(&[step], &[step + 1]);
+        }
+        assert_eq!(history.undo_len(), MAX_HISTORY_LENGTH);
+    }
+}
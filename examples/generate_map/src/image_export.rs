@@ -0,0 +1,163 @@
+//! PNG export of rendered maps and their thumbnails
+
+use {
+    rom_media_rs::image_rendering::bmp_sprite_decorators::TrueColorSurfaceSprite,
+    png::{Encoder, ColorType, BitDepth, Compression, FilterType},
+    std::{
+        fs::File,
+        io::{self, Write, BufWriter},
+        path::{Path, PathBuf}
+    }
+};
+
+/// Writes an 8-bit RGB image, `rgb` holds `width * height` pixel triplets row by row.
+/// Maps are made of repeating tiles, so Paeth filtering with the best compression
+/// keeps the files small
+pub fn write_png<W: Write>(writer: &mut W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    if rgb.len() != width * height * 3 || width == 0 || height == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "pixel data does not match image size"));
+    }
+    let mut encoder = Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(ColorType::RGB);
+    encoder.set_depth(BitDepth::Eight);
+    encoder.set_compression(Compression::Best);
+    encoder.set_filter(FilterType::Paeth);
+    // the image end chunk is written when the writer is dropped
+    encoder.write_header()?.write_image_data(rgb)?;
+    Ok(())
+}
+
+pub fn save_png(path: &Path, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_png(&mut writer, width, height, rgb)?;
+    writer.flush()
+}
+
+/// `map.png` gets its thumbnail written next to it as `map_thumb.png`
+pub fn thumbnail_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|it| it.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}_thumb.png", stem))
+}
+
+/// Saves the image and, with `thumbnail` given, a copy whose longer side fits into it
+pub fn save_png_with_thumbnail(
+    path: &Path,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+    thumbnail: Option<usize>
+) -> io::Result<()> {
+    save_png(path, width, height, rgb)?;
+    if let Some(max_side) = thumbnail {
+        let (thumb, thumb_width, thumb_height) = thumbnail_rgb(rgb, width, height, max_side);
+        save_png(&thumbnail_path(path), thumb_width, thumb_height, &thumb)?;
+    }
+    Ok(())
+}
+
+/// Converts pixels of a surface (stored as BGRA) into RGB triplets
+pub fn surface_to_rgb(surface: &TrueColorSurfaceSprite) -> Vec<u8> {
+    let bytes: &[u8] = bytemuck::cast_slice(surface.color_data());
+    let mut rgb = Vec::with_capacity(bytes.len() / 4 * 3);
+    for pixel in bytes.chunks(4) {
+        rgb.push(pixel[2]);
+        rgb.push(pixel[1]);
+        rgb.push(pixel[0]);
+    }
+    rgb
+}
+
+/// Draws non transparent pixels of a BGRA buffer over RGB triplets of the same size
+pub fn overlay_bgra(rgb: &mut [u8], bgra: &[u32]) {
+    for (pixel, &color) in rgb.chunks_mut(3).zip(bgra.iter()) {
+        if color == 0 { continue; }
+        let [b, g, r, _] = color.to_le_bytes();
+        pixel.copy_from_slice(&[r, g, b]);
+    }
+}
+
+/// Shrinks the image so that its longer side fits into `max_side`, every pixel of the result
+/// averages a square block of the source. A very narrow image keeps at least one pixel across,
+/// its blocks are cut at the border. Returns the pixels along with the new size
+pub fn thumbnail_rgb(rgb: &[u8], width: usize, height: usize, max_side: usize) -> (Vec<u8>, usize, usize) {
+    let max_side = max_side.max(1);
+    let factor = ((width.max(height) + max_side - 1) / max_side).max(1);
+    let thumb_width = (width / factor).max(1).min(width);
+    let thumb_height = (height / factor).max(1).min(height);
+    let mut thumb = Vec::with_capacity(thumb_width * thumb_height * 3);
+    for thumb_y in 0..thumb_height {
+        for thumb_x in 0..thumb_width {
+            let rows = thumb_y * factor..((thumb_y + 1) * factor).min(height);
+            let columns = thumb_x * factor..((thumb_x + 1) * factor).min(width);
+            let mut sum = [0u32; 3];
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    let pixel = &rgb[(y * width + x) * 3..(y * width + x) * 3 + 3];
+                    for channel in 0..3 {
+                        sum[channel] += pixel[channel] as u32;
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as u32;
+            thumb.extend(sum.iter().map(|&it| (it / count) as u8));
+        }
+    }
+    (thumb, thumb_width, thumb_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::io::Cursor
+    };
+
+    /// Checkered 32x32 tiles, roughly what a rendered map looks like to the encoder
+    fn tiled_rgb(width: usize, height: usize) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let shade = if (x / 32 + y / 32) % 2 == 0 { 0x20 } else { 0xC0 };
+                rgb.extend_from_slice(&[shade, (x % 32 * 8) as u8, (y % 32 * 8) as u8]);
+            }
+        }
+        rgb
+    }
+
+    #[test]
+    fn written_png_decodes_back() {
+        let (width, height) = (96, 64);
+        let rgb = tiled_rgb(width, height);
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, width, height, &rgb).unwrap();
+        assert!(bytes.len() < rgb.len() / 4, "{} bytes for {} bytes of pixels", bytes.len(), rgb.len());
+
+        let (info, mut reader) = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        assert_eq!((info.width, info.height), (width as u32, height as u32));
+        assert_eq!(info.color_type, ColorType::RGB);
+        let mut decoded = vec![0; info.buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, rgb);
+    }
+
+    #[test]
+    fn mismatching_pixel_data_is_rejected() {
+        assert!(write_png(&mut Vec::new(), 4, 4, &[0; 4 * 4 * 3 - 1]).is_err());
+        assert!(write_png(&mut Vec::new(), 0, 4, &[]).is_err());
+    }
+
+    #[test]
+    fn thumbnail_of_a_strip_keeps_both_sides() {
+        let (width, height) = (1000, 2);
+        let rgb: Vec<u8> = (0..width * height).flat_map(|idx| vec![(idx % 200) as u8, 0x40, 0x80]).collect();
+        let (thumb, thumb_width, thumb_height) = thumbnail_rgb(&rgb, width, height, 64);
+        assert!((1..=64).contains(&thumb_width));
+        assert_eq!(thumb_height, 1);
+        assert_eq!(thumb.len(), thumb_width * thumb_height * 3);
+        assert!(thumb.chunks(3).all(|pixel| pixel[1] == 0x40 && pixel[2] == 0x80));
+
+        let (thumb, thumb_width, thumb_height) = thumbnail_rgb(&rgb[..3 * 2], 1, 2, 1);
+        assert_eq!((thumb_width, thumb_height), (1, 1));
+        assert_eq!(thumb, vec![0, 0x40, 0x80]);
+    }
+}
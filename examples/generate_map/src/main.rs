@@ -1,18 +1,46 @@
 mod constants;
 mod heuristics;
 mod tiling;
+mod tileset;
 mod shaders;
 mod resource_loading;
+mod map_file;
+mod history;
+mod camera;
+mod image_export;
+mod batch;
+mod brush;
+mod noise;
+mod paths;
+mod walkability;
+mod objects;
+mod scatter;
+mod minimap;
 
 use {
     std::collections::VecDeque,
     std::sync::mpsc::{Receiver, Sender, channel},
     std::thread,
+    std::path::{Path, PathBuf},
+    rand::{thread_rng, Rng},
     crate::{
         constants::*,
         heuristics::*,
         tiling::*,
-        resource_loading::*
+        tileset::{TilesetDescription, load_tileset_description},
+        resource_loading::*,
+        map_file::MapFile,
+        history::History,
+        camera::{Camera, ZOOM_STEP},
+        batch::{batch_options_from_args, render_options_from_args, run_batch, run_render, make_choice_heuristic, generate_tile_modules, compose_tilemap},
+        noise::NoiseParams,
+        paths::route_through,
+        walkability::Walkability,
+        objects::{ObjectLayer, PlacedObject, SpriteCache, OBJECT_PALETTE, render_objects},
+        scatter::{ScatterSettings, DEFAULT_SCATTER_RULES, scatter_objects},
+        minimap::{Minimap, minimap_extent},
+        image_export::{save_png_with_thumbnail, surface_to_rgb, overlay_bgra},
+        brush::*
     },
     orom_miniquad::*,
     egui_integration::*,
@@ -23,7 +51,7 @@ use {
         blittable::{Blittable, BlitBuilder}
     },
     simple_tiled_wfc::{get_bits_set_count, BitsIterator, errors::WfcError},
-    simple_tiled_wfc::grid_generation::{ WfcContextBuilder, WfcModule }
+    simple_tiled_wfc::grid_generation::{ WfcContextBuilder, WfcModule, WfcEntropyChoiceHeuristic }
 };
 
 #[derive(PartialEq)]
@@ -36,7 +64,15 @@ enum IterationState {
 #[derive(PartialEq)]
 enum GeneralCommand {
     Collapse(CustomBitSet),
-    CollapseIteratively(CustomBitSet)
+    CollapseIteratively(CustomBitSet),
+    SaveMap,
+    LoadMap,
+    ExportImage,
+    Undo,
+    Redo,
+    NewMap(usize, usize),
+    /// Fill variants depend on the seed, so changing it redraws the map
+    Redraw
 }
 
 type CustomBitSet = [u8; 30];
@@ -54,6 +90,7 @@ struct Stage {
     info_text_bindings: Bindings,
     info_text_pipeline: Pipeline,
     surfaces: Surfaces,
+    tileset: TilesetDescription,
     terrain_gui_textures: Vec<Texture>,
     tiles: Vec<TileInfo>,
     modules: Vec<WfcModule<CustomBitSet>>,
@@ -72,75 +109,99 @@ struct Stage {
     command_queue: VecDeque<GeneralCommand>,
     draw_queue: VecDeque<(usize, usize, u8)>,
     iterative_speed: i32,
-    iterative_update_state: IterationState
+    iterative_update_state: IterationState,
+    map_path: String,
+    status_message: String,
+    history: History,
+    map_width: usize,
+    map_height: usize,
+    new_map_size: (usize, usize),
+    show_new_map_dialog: bool,
+    camera: Camera,
+    panning: bool,
+    mouse_pos: (f32, f32),
+    seed: u64,
+    brush: Brush,
+    paint_mode: PaintMode,
+    rect_start: Option<(usize, usize)>,
+    weights: TerrainWeights,
+    use_heightmap: bool,
+    noise_params: NoiseParams,
+    path_waypoints: Vec<(usize, usize)>,
+    show_walkability: bool,
+    walkability: Option<Walkability>,
+    walkability_dirty: bool,
+    repair_attempts_left: usize,
+    object_layer: ObjectLayer,
+    sprite_cache: SpriteCache,
+    selected_object: usize,
+    object_frame: u16,
+    objects_dirty: bool,
+    scatter_settings: ScatterSettings,
+    scatter_after_collapse: bool,
+    minimap: Minimap,
+    minimap_texture: Texture,
+    export_thumbnail: bool
 }
 
 impl Stage {
-    pub fn new(ctx: &mut Context) -> Stage {
+    pub fn new(ctx: &mut Context, tileset: TilesetDescription) -> Stage {
+        let (map_width, map_height) = (DEFAULT_MAP_WIDTH, DEFAULT_MAP_HEIGHT);
         let tile_resolution = (
-            WIDTH as f32,
-            HEIGHT as f32
+            map_width as f32,
+            map_height as f32
         );
 
         let (iterative_results_transmitter, iterative_results_receiver) = channel();
         let (compound_results_transmitter, compound_results_receiver) = channel();
 
         let black_square = TrueColorSurfaceSprite::new(32, 32);
-        let atlas = load_atlas_texture();
-        let tiles = make_tiling_lookup();
+        let atlas = load_atlas_texture(&tileset);
+        let tiles = make_tiling_lookup(&tileset);
         let modules = make_module_set(&tiles);
+        let weights = TerrainWeights::new(&tileset);
+
+        let tile_modules = generate_tile_modules(
+            &tiles,
+            &modules,
+            map_width,
+            map_height,
+            make_choice_heuristic(
+                &tileset,
+                &tiles,
+                map_width,
+                map_height,
+                AvailableTiles::default().make_bitset(&tiles),
+                &weights,
+                None,
+                DEFAULT_SEED
+            ),
+            DEFAULT_SEED
+        ).unwrap_or_else(|_| vec![fill_tile(&tiles, BASE_TERRAIN); map_width * map_height]);
+        let stage_surface = compose_tilemap(
+            &atlas,
+            &tiles,
+            map_width,
+            map_height,
+            &tile_modules,
+            DEFAULT_SEED
+        );
 
-        let (stage_surface, tile_modules) = {
-            let mut stage_surface = TrueColorSurfaceSprite::new(
-                STAGE_SURFACE_W,
-                STAGE_SURFACE_H
-            );
-            let mut wfc_context = WfcContextBuilder::new(&modules, WIDTH, HEIGHT).build();
-
-            wfc_context.collapse(100, compound_results_transmitter.clone());
-
-            let tile_modules = compound_results_receiver.recv()
-                .unwrap()
-                .unwrap_or_else(|_| vec![4; WIDTH * HEIGHT]);
-
-            for idx in 0..tile_modules.len() {
-                let row = idx / WIDTH;
-                let column = idx % WIDTH;
-                let tile_id = tile_modules[idx];
-                let tile_info = &tiles[tile_id];
-                BlitBuilder::try_create(&mut stage_surface, &atlas)
-                    .expect("failed to create blit builder")
-                    .with_source_subrect(tile_info.tile_x, tile_info.tile_y, 32, 32)
-                    .with_dest_pos(column as i32 * 32, row as i32 * 32)
-                    .blit();
-            }
-            (stage_surface, tile_modules)
-        };
-
-        let terrain_gui_textures = load_gui_textures(ctx);
+        let terrain_gui_textures = load_gui_textures(ctx, &tileset);
+        let minimap = Minimap::new(&tileset, &tiles, &tile_modules);
+        let minimap_texture = make_overlay_texture(ctx, map_width, map_height);
 
         let tilemap_bindings = {
-            let texture = {
-                let casted = bytemuck::cast_slice(stage_surface.color_data());
-                Texture::from_data_and_format(
-                    ctx,
-                    &casted,
-                    TextureParams {
-                        format: TextureFormat::RGBA8,
-                        wrap: TextureWrap::Clamp,
-                        filter: FilterMode::Linear,
-                        width: stage_surface.get_width() as u32,
-                        height: stage_surface.get_height() as u32
-                    }
-                )
-            };
+            let texture = make_surface_texture(ctx, &stage_surface);
+            let overlay = make_overlay_texture(ctx, map_width, map_height);
+            let objects = make_overlay_texture(ctx, map_width * 32, map_height * 32);
 
             let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &VERTICES);
             let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &[0u16, 1, 2, 0, 2, 3]);
             Bindings {
                 vertex_buffers: vec![vertex_buffer],
                 index_buffer,
-                images: vec![texture],
+                images: vec![texture, overlay, objects],
             }
         };
 
@@ -224,6 +285,7 @@ impl Stage {
             info_text_bindings,
             info_text_pipeline,
             surfaces: Surfaces { atlas, black_square, stage_surface},
+            tileset,
             terrain_gui_textures,
             tiles,
             tile_modules,
@@ -234,7 +296,7 @@ impl Stage {
             tile_resolution,
             show_grid: true,
             show_ui: true,
-            current_tool: GRASS,
+            current_tool: BASE_TERRAIN,
             iterative_results_receiver,
             iterative_results_transmitter,
             compound_results_receiver,
@@ -243,25 +305,112 @@ impl Stage {
             draw_queue: VecDeque::new(),
             egui,
             iterative_speed: 10,
-            iterative_update_state: IterationState::Idle
+            iterative_update_state: IterationState::Idle,
+            map_path: "map.orommap".to_string(),
+            status_message: String::new(),
+            history: History::new(),
+            map_width,
+            map_height,
+            new_map_size: (map_width, map_height),
+            show_new_map_dialog: false,
+            camera: Camera::new(),
+            panning: false,
+            mouse_pos: (0.0, 0.0),
+            seed: DEFAULT_SEED,
+            brush: Brush::new(),
+            paint_mode: PaintMode::Brush,
+            rect_start: None,
+            weights,
+            use_heightmap: false,
+            noise_params: NoiseParams::default(),
+            path_waypoints: Vec::new(),
+            show_walkability: false,
+            walkability: None,
+            walkability_dirty: true,
+            repair_attempts_left: 0,
+            object_layer: ObjectLayer::new(),
+            sprite_cache: SpriteCache::new(),
+            selected_object: 0,
+            object_frame: 0,
+            objects_dirty: false,
+            scatter_settings: ScatterSettings::default(),
+            scatter_after_collapse: false,
+            minimap,
+            minimap_texture,
+            export_thumbnail: false
         }
     }
 
     fn enqueue_draw(&mut self) {
-        let row = self.tile_selection.1 as usize;
-        let column = self.tile_selection.0 as usize;
-        if self.draw_queue.is_empty() {
-            self.draw_queue.push_back((row, column, self.current_tool));
-        } else {
-            let mut last = self.draw_queue.pop_back().unwrap();
-            if last.0 == row && last.1 == column {
-                if last.2 != self.current_tool {
-                    last.2 = self.current_tool;
+        let (column, row) = self.tile_selection;
+        let cells = self.brush.footprint(row, column, self.map_width, self.map_height);
+        self.enqueue_cells(&cells, self.current_tool);
+    }
+
+    fn enqueue_rectangle(&mut self) {
+        if let Some(rect_start) = self.rect_start.take() {
+            let cells = rectangle_cells(rect_start, self.tile_selection);
+            self.enqueue_cells(&cells, self.current_tool);
+        }
+    }
+
+    fn enqueue_flood_fill(&mut self) {
+        let (column, row) = self.tile_selection;
+        let (cells, truncated) = flood_fill_region(
+            &self.tiles,
+            &self.tile_modules,
+            self.map_width,
+            self.map_height,
+            (row, column)
+        );
+        if truncated {
+            self.status_message = format!("Fill is limited to {} tiles", MAX_FILL_CELLS);
+        }
+        self.enqueue_cells(&cells, self.current_tool);
+    }
+
+    fn add_path_waypoint(&mut self) {
+        let (column, row) = self.tile_selection;
+        if self.path_waypoints.last() != Some(&(row, column)) {
+            self.path_waypoints.push((row, column));
+        }
+    }
+
+    /// Routes the current tool terrain through the waypoints. Cells which already have
+    /// the terrain are the cheapest, the base terrain is cheap too since most transitions border it
+    fn enqueue_path(&mut self) {
+        let waypoints = std::mem::take(&mut self.path_waypoints);
+        if waypoints.is_empty() { return; }
+        let route = {
+            let (tiles, tile_modules, width) = (&self.tiles, &self.tile_modules, self.map_width);
+            let tool = self.current_tool;
+            route_through(self.map_width, self.map_height, &waypoints, |row, column| {
+                match tiles[tile_modules[row * width + column]].dominant_terrain() {
+                    terrain if terrain == tool => 1,
+                    BASE_TERRAIN => 2,
+                    _ => 6
                 }
-                self.draw_queue.push_back(last);
-            } else {
-                self.draw_queue.push_back(last);
-                self.draw_queue.push_back((row, column, self.current_tool))
+            })
+        };
+        match route {
+            Some(route) => {
+                self.history.begin_stroke();
+                self.enqueue_cells(&route, self.current_tool);
+            }
+            None => self.status_message = "Failed to route the path".to_string()
+        }
+    }
+
+    fn enqueue_cells(&mut self, cells: &[(usize, usize)], tool: u8) {
+        for &(row, column) in cells {
+            let tile = &self.tiles[self.tile_modules[row * self.map_width + column]];
+            let already_painted = [tile.north_west, tile.north_east, tile.south_west, tile.south_east]
+                .iter()
+                .all(|&terrain| terrain == tool);
+            match self.draw_queue.iter_mut().find(|it| it.0 == row && it.1 == column) {
+                Some(queued) => queued.2 = tool,
+                None if already_painted => {}
+                None => self.draw_queue.push_back((row, column, tool))
             }
         }
     }
@@ -278,22 +427,75 @@ impl Stage {
             .show(&egui_ctx, |ui| {
                 {
                     ui.vertical_centered_justified(|ui| {
+                        if ui.button("New map...").clicked() {
+                            self.new_map_size = (self.map_width, self.map_height);
+                            self.show_new_map_dialog = true;
+                        }
                         if ui.button("Collapse").clicked() {
                             self.command_queue.push_back(
-                                GeneralCommand::Collapse(self.available_tiles.make_bitset())
+                                GeneralCommand::Collapse(self.available_tiles.make_bitset(&self.tiles))
                             );
                         }
                         if ui.button("Collapse iteratively").clicked() {
                             self.command_queue.push_back(
-                                GeneralCommand::CollapseIteratively(self.available_tiles.make_bitset())
+                                GeneralCommand::CollapseIteratively(self.available_tiles.make_bitset(&self.tiles))
                             );
                         }
                         ui.separator();
+                        ui.horizontal(|ui| {
+                            let undo_text = format!("Undo ({})", self.history.undo_len());
+                            if ui.add(egui::Button::new(undo_text).enabled(self.history.undo_len() > 0))
+                                .on_hover_text("ctrl+z")
+                                .clicked()
+                            {
+                                self.command_queue.push_back(GeneralCommand::Undo);
+                            }
+                            let redo_text = format!("Redo ({})", self.history.redo_len());
+                            if ui.add(egui::Button::new(redo_text).enabled(self.history.redo_len() > 0))
+                                .on_hover_text("ctrl+y")
+                                .clicked()
+                            {
+                                self.command_queue.push_back(GeneralCommand::Redo);
+                            }
+                        });
+                        ui.separator();
                         ui.add(egui::Checkbox::new(&mut self.show_grid, "Show grid (space)"));
+                        if ui.checkbox(&mut self.show_walkability, "Show isolated areas").changed() {
+                            self.walkability_dirty = true;
+                        }
+                        if let Some(walkability) = &self.walkability {
+                            ui.label(format!(
+                                "Regions: {}, isolated tiles: {}",
+                                walkability.region_sizes.len(),
+                                walkability.isolated_tile_count()
+                            ));
+                        }
+                        if ui.button("Repair connectivity").clicked() {
+                            self.repair_attempts_left = MAX_REPAIR_ATTEMPTS;
+                        }
                         ui.separator();
                         ui.label("Iterative speed:");
                         ui.add(egui::Slider::new(&mut self.iterative_speed, 1..=300));
                         ui.separator();
+                        ui.text_edit_singleline(&mut self.map_path);
+                        ui.horizontal(|ui| {
+                            if ui.button("Save (ctrl+s)").clicked() {
+                                self.command_queue.push_back(GeneralCommand::SaveMap);
+                            }
+                            if ui.button("Load (ctrl+o)").clicked() {
+                                self.command_queue.push_back(GeneralCommand::LoadMap);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("Export image").clicked() {
+                                self.command_queue.push_back(GeneralCommand::ExportImage);
+                            }
+                            ui.checkbox(&mut self.export_thumbnail, "with thumbnail");
+                        });
+                        if !self.status_message.is_empty() {
+                            ui.label(&self.status_message);
+                        }
+                        ui.separator();
 
                         if ui.button("Quit (esc)").clicked() {
                             std::process::exit(0);
@@ -302,6 +504,34 @@ impl Stage {
                 }
             });
 
+        if self.show_new_map_dialog {
+            egui::Window::new("new map")
+                .resizable(false)
+                .collapsible(false)
+                .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(&egui_ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Width: ");
+                        ui.add(egui::DragValue::new(&mut self.new_map_size.0)
+                            .clamp_range(MIN_MAP_SIDE..=MAX_MAP_SIDE));
+                        ui.label("Height:");
+                        ui.add(egui::DragValue::new(&mut self.new_map_size.1)
+                            .clamp_range(MIN_MAP_SIDE..=MAX_MAP_SIDE));
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Create").clicked() {
+                            self.command_queue.push_back(
+                                GeneralCommand::NewMap(self.new_map_size.0, self.new_map_size.1)
+                            );
+                            self.show_new_map_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_new_map_dialog = false;
+                        }
+                    });
+                });
+        }
+
         egui::Window::new("brush")
             .min_width(40.0)
             .default_width(40.0)
@@ -310,19 +540,16 @@ impl Stage {
             .show(&egui_ctx, |ui| {
                 {
                     ui.vertical_centered_justified(|ui| {
-                        for setting in &[
-                            (LAND,       "     Land (1)"),
-                            (GRASS,      "    Grass (2)"),
-                            (PLATEAU,    "  Plateau (3)"),
-                            (SAND,       "    Sands (4)"),
-                            (SAVANNAH,   " Savannah (5)"),
-                            (ROCKS,      "    Rocks (6)"),
-                            (HIGH_ROCKS, "High rocks(7)"),
-                            (WATER,      "     Water(8)"),
-                            (ROAD,       "     Road (9)")
-                        ] {
+                        for terrain in 0..self.tileset.terrains.len() {
+                            let description = &self.tileset.terrains[terrain];
+                            let color = description.color;
+                            // digit keys select the first nine terrains
+                            let setting = (terrain as u8, if terrain < 9 {
+                                format!("{:>9} ({})", description.label, terrain + 1)
+                            } else {
+                                format!("{:>13}", description.label)
+                            });
                             ui.horizontal(|ui| {
-                                let color = get_tool_color(setting.0);
                                 let coeff = if setting.0 == self.current_tool { 255.0 } else { 200.0 };
                                 let text_color = Color32::from_rgb(
                                     (color.0 * coeff).min(coeff) as u8,
@@ -345,7 +572,7 @@ impl Stage {
                                     [24.0, 24.0]
                                 ).tint(tint_color);
 
-                                ui.add(egui::Label::new(setting.1)
+                                ui.add(egui::Label::new(&setting.1)
                                     .strong()
                                     .text_style(TextStyle::Monospace)
                                     .text_color(text_color));
@@ -354,93 +581,187 @@ impl Stage {
                                 }
                             });
                         }
+                        ui.separator();
+                        ui.radio_value(&mut self.paint_mode, PaintMode::Brush, "Brush (b)");
+                        ui.radio_value(&mut self.paint_mode, PaintMode::Rectangle, "Rectangle (r)");
+                        ui.radio_value(&mut self.paint_mode, PaintMode::FloodFill, "Fill (f)");
+                        ui.radio_value(&mut self.paint_mode, PaintMode::Path, "Path (p)");
+                        ui.radio_value(&mut self.paint_mode, PaintMode::Objects, "Objects (o)");
+                        if self.paint_mode == PaintMode::Path {
+                            ui.label(format!("Waypoints: {}", self.path_waypoints.len()));
+                            ui.horizontal(|ui| {
+                                if ui.button("Build (enter)").clicked() {
+                                    self.enqueue_path();
+                                }
+                                if ui.button("Clear (bksp)").clicked() {
+                                    self.path_waypoints.clear();
+                                }
+                            });
+                        }
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut self.brush.shape, BrushShape::Square, "Square");
+                            ui.radio_value(&mut self.brush.shape, BrushShape::Circle, "Circle");
+                        });
+                        ui.label("Radius ([ and ]):");
+                        ui.add(egui::Slider::new(&mut self.brush.radius, 0..=MAX_BRUSH_RADIUS));
                     });
                 }
             });
 
+        if self.paint_mode == PaintMode::Objects {
+            egui::Window::new("objects")
+                .resizable(false)
+                .anchor(Align2::LEFT_BOTTOM, [0.0, 0.0])
+                .show(&egui_ctx, |ui| {
+                    ui.label("Left click places, right click removes");
+                    egui::ScrollArea::from_max_height(240.0).show(ui, |ui| {
+                        for (i, path) in OBJECT_PALETTE.iter().enumerate() {
+                            if ui.selectable_label(i == self.selected_object, *path).clicked() {
+                                self.selected_object = i;
+                                self.object_frame = 0;
+                            }
+                        }
+                    });
+                    let frame_count = self.sprite_cache.frame_count(OBJECT_PALETTE[self.selected_object]);
+                    ui.horizontal(|ui| {
+                        ui.label("Frame:");
+                        ui.add(egui::DragValue::new(&mut self.object_frame)
+                            .clamp_range(0..=frame_count.max(1) as u16 - 1));
+                        ui.label(format!("of {}", frame_count));
+                    });
+                    ui.label(format!("Objects on the map: {}", self.object_layer.objects.len()));
+                });
+        }
+
+        egui::Window::new("minimap")
+            .resizable(false)
+            .anchor(Align2::RIGHT_BOTTOM, [0.0, 0.0])
+            .show(&egui_ctx, |ui| {
+                let (width, height) = minimap_extent(self.map_width, self.map_height);
+                let (rect, response) = ui.allocate_exact_size(
+                    egui::vec2(width, height),
+                    egui::Sense::click_and_drag()
+                );
+                let painter = ui.painter();
+                painter.image(
+                    TextureId::User(self.minimap_texture.gl_internal_id() as u64),
+                    rect,
+                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                    Color32::WHITE
+                );
+
+                // Tiles are scaled to points uniformly along both axes
+                let points_per_tile = width / self.map_width as f32;
+                let (visible_w, visible_h) = self.camera.visible_tiles();
+                let viewport = egui::Rect::from_min_size(
+                    rect.min + egui::vec2(self.camera.x, self.camera.y) * points_per_tile,
+                    egui::vec2(visible_w, visible_h) * points_per_tile
+                ).intersect(rect);
+                painter.rect_stroke(viewport, 0.0, (1.0, Color32::WHITE));
+
+                if response.clicked() || response.dragged() {
+                    if let Some(pos) = response.hover_pos() {
+                        let tile = (pos - rect.min) / points_per_tile;
+                        self.camera.center_on(tile.x, tile.y);
+                        self.camera.clamp_to_map(self.map_width, self.map_height);
+                    }
+                }
+            });
+
         egui::Window::new("generation settings")
             .anchor(Align2::CENTER_BOTTOM, [0.0, 0.0])
             .show(&egui_ctx, |ui| {
                 ui.vertical(|ui| {
-                    ui.horizontal(|ui| {
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[LAND as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.land, "");
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[GRASS as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.grass, "");
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[PLATEAU as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.plateau, "");
+                    let terrain_count = self.tileset.terrains.len();
+                    for row in (0..terrain_count).step_by(3) {
+                        ui.horizontal(|ui| {
+                            for terrain in row..(row + 3).min(terrain_count) {
+                                ui.image(
+                                    TextureId::User(
+                                        self.terrain_gui_textures[terrain]
+                                            .gl_internal_id() as u64
+                                    ),
+                                    [20.0, 20.0]
+                                );
+                                let mut allowed = self.available_tiles.contains(terrain as u8);
+                                if ui.checkbox(&mut allowed, "")
+                                    .on_hover_text(&self.tileset.terrains[terrain].label)
+                                    .changed()
+                                {
+                                    self.available_tiles.set(terrain as u8, allowed);
+                                }
+                            }
+                        });
+                        ui.spacing();
+                    }
+                    ui.collapsing("Terrain weights", |ui| {
+                        for terrain in 0..self.tileset.terrains.len() {
+                            ui.horizontal(|ui| {
+                                ui.image(
+                                    TextureId::User(
+                                        self.terrain_gui_textures[terrain].gl_internal_id() as u64
+                                    ),
+                                    [20.0, 20.0]
+                                );
+                                ui.add(egui::Slider::new(&mut self.weights.terrains[terrain], 0.0..=10.0)
+                                    .text(&self.tileset.terrains[terrain].label));
+                            });
+                        }
                     });
-                    ui.spacing();
-                    ui.horizontal(|ui| {
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[SAND as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.sand, "");
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[SAVANNAH as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.savannah, "");
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[ROCKS as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.rocks, "");
+                    ui.collapsing("Transition weights", |ui| {
+                        for set in 0..self.weights.transition_sets.len() {
+                            let tile = self.tiles.iter().find(|it| it.transition_set == set).unwrap();
+                            let label = format!(
+                                "{}/{}",
+                                self.tileset.terrains[tile.outer as usize].label,
+                                self.tileset.terrains[tile.inner as usize].label
+                            );
+                            ui.add(egui::Slider::new(&mut self.weights.transition_sets[set], 0.0..=10.0)
+                                .text(label));
+                        }
                     });
+                    ui.collapsing("Scatter objects", |ui| {
+                        ui.add(egui::Slider::new(&mut self.scatter_settings.density, 0.0..=2.0)
+                            .text("density"));
+                        ui.add(egui::Slider::new(&mut self.scatter_settings.min_distance, 1.0..=6.0)
+                            .text("spacing"));
+                        ui.checkbox(&mut self.scatter_after_collapse, "Scatter after collapse");
+                        ui.horizontal(|ui| {
+                            if ui.button("Scatter now").clicked() {
+                                self.scatter();
+                            }
+                            if ui.button("Clear objects").clicked() {
+                                self.object_layer.clear();
+                                self.objects_dirty = true;
+                            }
+                        });
+                    });
+                    ui.checkbox(&mut self.use_heightmap, "Follow a noise heightmap");
+                    if self.use_heightmap {
+                        ui.collapsing("Heightmap", |ui| {
+                            let params = &mut self.noise_params;
+                            ui.add(egui::Slider::new(&mut params.scale, 2.0..=64.0).text("scale"));
+                            ui.add(egui::Slider::new(&mut params.octaves, 1..=6).text("octaves"));
+                            ui.add(egui::Slider::new(&mut params.persistence, 0.1..=0.9).text("persistence"));
+                            ui.add(egui::Slider::new(&mut params.water_level, 0.0..=1.0).text("water level"));
+                            ui.add(egui::Slider::new(&mut params.rocks_level, 0.0..=1.0).text("rocks level"));
+                            ui.add(egui::Slider::new(&mut params.high_rocks_level, 0.0..=1.0)
+                                .text("high rocks level"));
+                            params.rocks_level = params.rocks_level.max(params.water_level);
+                            params.high_rocks_level = params.high_rocks_level.max(params.rocks_level);
+                        });
+                    }
                     ui.spacing();
                     ui.horizontal(|ui| {
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[HIGH_ROCKS as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.high_rocks, "");
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[WATER as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.water, "");
-                        ui.image(
-                            TextureId::User(
-                                self.terrain_gui_textures[ROAD as usize]
-                                    .gl_internal_id() as u64
-                            ),
-                            [20.0, 20.0]
-                        );
-                        ui.checkbox(&mut self.available_tiles.road, "");
+                        ui.label("Seed:");
+                        if ui.add(egui::DragValue::new(&mut self.seed).speed(1.0)).changed() {
+                            self.command_queue.push_back(GeneralCommand::Redraw);
+                        }
+                        if ui.button("Randomize").clicked() {
+                            self.seed = thread_rng().gen::<u32>() as u64;
+                            self.command_queue.push_back(GeneralCommand::Redraw);
+                        }
                     });
                 });
             });
@@ -461,12 +782,63 @@ impl EventHandler for Stage {
                 GeneralCommand::CollapseIteratively(tileset) => {
                     self.initiate_iterative_collapse(tileset);
                 }
+                GeneralCommand::SaveMap => {
+                    self.save_map();
+                }
+                GeneralCommand::LoadMap => {
+                    self.load_map(ctx);
+                }
+                GeneralCommand::ExportImage => {
+                    self.export_image();
+                }
+                GeneralCommand::Undo => {
+                    self.undo(ctx);
+                }
+                GeneralCommand::Redo => {
+                    self.redo(ctx);
+                }
+                GeneralCommand::NewMap(width, height) => {
+                    let resized = width != self.map_width || height != self.map_height;
+                    self.object_layer.clear();
+                    // Filling with the base terrain and collapsing is a single step to undo
+                    self.history.begin_stroke();
+                    self.resize_map(ctx, width, height, vec![fill_tile(&self.tiles, BASE_TERRAIN); width * height]);
+                    self.collapse(ctx, self.available_tiles.make_bitset(&self.tiles));
+                    if resized {
+                        // there is nothing to undo to on a map of other dimensions
+                        self.history.clear();
+                    } else {
+                        self.history.end_stroke();
+                    }
+                }
+                GeneralCommand::Redraw => {
+                    self.redraw_tilemap(ctx);
+                }
             }
             return; // Process one command at a time. Do not flush draw queue if there was a command
         }
         if !self.draw_queue.is_empty() {
             self.flush_draw_queue(ctx);
         }
+        if !self.mouse_down && self.draw_queue.is_empty() {
+            // The stroke is closed only after everything it has queued is drawn
+            self.history.end_stroke();
+        }
+        if self.objects_dirty {
+            self.refresh_objects(ctx);
+        }
+        if self.minimap.dirty {
+            self.minimap_texture.update(ctx, &self.minimap.bytes);
+            self.minimap.dirty = false;
+        }
+        if self.draw_queue.is_empty() {
+            if self.walkability_dirty {
+                self.refresh_walkability(ctx);
+            }
+            if self.repair_attempts_left > 0 {
+                self.repair_step();
+            }
+        }
     }
 
     fn draw(&mut self, ctx: &mut Context) {
@@ -481,11 +853,26 @@ impl EventHandler for Stage {
             ctx.apply_pipeline(&self.tilemap_pipeline);
             ctx.apply_bindings(&self.tilemap_bindings);
 
+            let (view_offset, view_scale) = self.camera.uv_transform(self.map_width, self.map_height);
+            let (brush_radius, brush_shape) = match (self.paint_mode, self.rect_start) {
+                (PaintMode::Brush, _) => (
+                    self.brush.radius as f32,
+                    if self.brush.shape == BrushShape::Circle { 1.0 } else { 0.0 }
+                ),
+                (PaintMode::Rectangle, Some(_)) => (0.0, 2.0),
+                _ => (0.0, 0.0)
+            };
+            let rect_start = self.rect_start.unwrap_or(self.tile_selection);
             ctx.apply_uniforms(&shaders::TilemapUniforms {
                 mouse_pos: (self.tile_selection.0 as f32, self.tile_selection.1 as f32),
                 tile_resolution: self.tile_resolution,
                 grid_color: if self.show_grid {(0.0, 0.4, 0.7)} else {(0.0, 0.0, 0.0)} ,
-                tool_color: get_tool_color(self.current_tool)
+                tool_color: self.tileset.terrain_color(self.current_tool),
+                view_offset,
+                view_scale,
+                brush_radius,
+                brush_shape,
+                rect_start: (rect_start.0 as f32, rect_start.1 as f32)
             });
 
             ctx.draw(0, 6, 1);
@@ -536,21 +923,25 @@ impl EventHandler for Stage {
     fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32) {
         let screen_size = ctx.screen_size();
         self.egui.mouse_motion_event(ctx, x, y);
+        let (last_x, last_y) = self.mouse_pos;
+        self.mouse_pos = (x, y);
+        if self.panning {
+            self.camera.pan_by_pixels(last_x - x, last_y - y, screen_size);
+            self.camera.clamp_to_map(self.map_width, self.map_height);
+            return;
+        }
         if !self.egui.egui_ctx().is_pointer_over_area() {
-            let tile_selection = (
-                (x / screen_size.0 * WIDTH as f32)
-                    .trunc()
-                    .max(0.0) as usize,
-                (y / screen_size.1 * HEIGHT as f32)
-                    .trunc()
-                    .max(0.0) as usize
-            );
+            let (tile_x, tile_y) = self.camera.screen_to_tile(x, y, screen_size);
+            if tile_x < 0.0 || tile_y < 0.0 {
+                return;
+            }
+            let tile_selection = (tile_x.trunc() as usize, tile_y.trunc() as usize);
+            if tile_selection.0 >= self.map_width || tile_selection.1 >= self.map_height {
+                return;
+            }
 
-            self.tile_selection = (
-                tile_selection.0.min(WIDTH - 1),
-                tile_selection.1.min(HEIGHT - 1)
-            );
-            if self.mouse_down {
+            self.tile_selection = tile_selection;
+            if self.mouse_down && self.paint_mode == PaintMode::Brush {
                 self.enqueue_draw();
             }
         }
@@ -558,6 +949,10 @@ impl EventHandler for Stage {
 
     fn mouse_wheel_event(&mut self, ctx: &mut Context, dx: f32, dy: f32) {
         self.egui.mouse_wheel_event(ctx, dx, dy);
+        if self.egui.egui_ctx().is_pointer_over_area() || dy == 0.0 { return; }
+        let factor = if dy > 0.0 { ZOOM_STEP } else { 1.0 / ZOOM_STEP };
+        self.camera.zoom_at(factor, self.mouse_pos.0, self.mouse_pos.1, ctx.screen_size());
+        self.camera.clamp_to_map(self.map_width, self.map_height);
     }
 
     fn mouse_button_down_event(
@@ -569,9 +964,26 @@ impl EventHandler for Stage {
     ) {
         self.egui.mouse_button_down_event(ctx, button, x, y);
         if !self.egui.egui_ctx().is_pointer_over_area() {
-            if let MouseButton::Left = button {
-                self.mouse_down = true;
-                self.enqueue_draw();
+            match button {
+                MouseButton::Left => {
+                    self.mouse_down = true;
+                    self.history.begin_stroke();
+                    match self.paint_mode {
+                        PaintMode::Brush => self.enqueue_draw(),
+                        PaintMode::Rectangle => self.rect_start = Some(self.tile_selection),
+                        PaintMode::FloodFill => self.enqueue_flood_fill(),
+                        PaintMode::Path => self.add_path_waypoint(),
+                        PaintMode::Objects => self.place_object()
+                    }
+                }
+                MouseButton::Right if self.paint_mode == PaintMode::Objects => {
+                    self.remove_object();
+                }
+                MouseButton::Middle => {
+                    self.mouse_pos = (x, y);
+                    self.panning = true;
+                }
+                _ => {}
             }
         }
     }
@@ -584,8 +996,14 @@ impl EventHandler for Stage {
         y: f32,
     ) {
         self.egui.mouse_button_up_event(ctx, button, x, y);
+        if let MouseButton::Middle = button {
+            self.panning = false;
+        }
         if !self.egui.egui_ctx().is_pointer_over_area() {
             if let MouseButton::Left = button {
+                if self.paint_mode == PaintMode::Rectangle {
+                    self.enqueue_rectangle();
+                }
                 self.mouse_down = false;
             }
         }
@@ -601,20 +1019,41 @@ impl EventHandler for Stage {
 
     fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods, _repeat: bool) {
         self.egui.key_down_event(ctx, keycode, keymods);
+        if self.egui.egui_ctx().wants_keyboard_input() { return; }
+        let scroll_step = 2.0 / self.camera.zoom;
+        match keycode {
+            KeyCode::Left => self.camera.pan_by_tiles(-scroll_step, 0.0),
+            KeyCode::Right => self.camera.pan_by_tiles(scroll_step, 0.0),
+            KeyCode::Up => self.camera.pan_by_tiles(0.0, -scroll_step),
+            KeyCode::Down => self.camera.pan_by_tiles(0.0, scroll_step),
+            _ => return
+        }
+        self.camera.clamp_to_map(self.map_width, self.map_height);
     }
 
     fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
         self.egui.key_up_event(keycode, keymods);
+        if self.egui.egui_ctx().wants_keyboard_input() { return; }
+        if let Some(terrain) = terrain_hotkey(keycode) {
+            if (terrain as usize) < self.tileset.terrains.len() {
+                self.current_tool = terrain;
+            }
+            return;
+        }
         match keycode {
-            KeyCode::Key1 => self.current_tool = LAND,
-            KeyCode::Key2 => self.current_tool = GRASS,
-            KeyCode::Key3 => self.current_tool = PLATEAU,
-            KeyCode::Key4 => self.current_tool = SAND,
-            KeyCode::Key5 => self.current_tool = SAVANNAH,
-            KeyCode::Key6 => self.current_tool = ROCKS,
-            KeyCode::Key7 => self.current_tool = HIGH_ROCKS,
-            KeyCode::Key8 => self.current_tool = WATER,
-            KeyCode::Key9 => self.current_tool = ROAD,
+            KeyCode::S if keymods.ctrl => self.command_queue.push_back(GeneralCommand::SaveMap),
+            KeyCode::O if keymods.ctrl => self.command_queue.push_back(GeneralCommand::LoadMap),
+            KeyCode::Z if keymods.ctrl => self.command_queue.push_back(GeneralCommand::Undo),
+            KeyCode::Y if keymods.ctrl => self.command_queue.push_back(GeneralCommand::Redo),
+            KeyCode::B => self.paint_mode = PaintMode::Brush,
+            KeyCode::R => self.paint_mode = PaintMode::Rectangle,
+            KeyCode::F => self.paint_mode = PaintMode::FloodFill,
+            KeyCode::P => self.paint_mode = PaintMode::Path,
+            KeyCode::O => self.paint_mode = PaintMode::Objects,
+            KeyCode::Enter if self.paint_mode == PaintMode::Path => self.enqueue_path(),
+            KeyCode::Backspace => self.path_waypoints.clear(),
+            KeyCode::LeftBracket => self.brush.radius = self.brush.radius.saturating_sub(1),
+            KeyCode::RightBracket => self.brush.radius = (self.brush.radius + 1).min(MAX_BRUSH_RADIUS),
             KeyCode::Space => self.show_grid = !self.show_grid,
             KeyCode::Escape => ctx.quit(),
             _ => {}
@@ -623,7 +1062,90 @@ impl EventHandler for Stage {
 }
 
 
+fn make_surface_texture(ctx: &mut Context, surface: &TrueColorSurfaceSprite) -> Texture {
+    let casted = bytemuck::cast_slice(surface.color_data());
+    Texture::from_data_and_format(
+        ctx,
+        &casted,
+        TextureParams {
+            format: TextureFormat::RGBA8,
+            wrap: TextureWrap::Clamp,
+            filter: FilterMode::Linear,
+            width: surface.get_width() as u32,
+            height: surface.get_height() as u32
+        }
+    )
+}
+
+fn make_overlay_texture(ctx: &mut Context, width: usize, height: usize) -> Texture {
+    Texture::from_data_and_format(
+        ctx,
+        &vec![0u8; width * height * 4],
+        TextureParams {
+            format: TextureFormat::RGBA8,
+            wrap: TextureWrap::Clamp,
+            filter: FilterMode::Nearest,
+            width: width as u32,
+            height: height as u32
+        }
+    )
+}
+
+/// Terrain picked by the digit keys: 1 selects the first terrain of the tileset, 9 the ninth
+fn terrain_hotkey(keycode: KeyCode) -> Option<u8> {
+    match keycode {
+        KeyCode::Key1 => Some(0),
+        KeyCode::Key2 => Some(1),
+        KeyCode::Key3 => Some(2),
+        KeyCode::Key4 => Some(3),
+        KeyCode::Key5 => Some(4),
+        KeyCode::Key6 => Some(5),
+        KeyCode::Key7 => Some(6),
+        KeyCode::Key8 => Some(7),
+        KeyCode::Key9 => Some(8),
+        _ => None
+    }
+}
+
+/// `--tileset <path>` overrides the tileset, otherwise `assets/tileset.ron` is looked up
+/// next to the executable and then at `DEFAULT_TILESET_PATH`
+fn tileset_path_from_args() -> PathBuf {
+    let from_args = std::env::args()
+        .skip_while(|arg| arg != "--tileset")
+        .nth(1)
+        .map(PathBuf::from);
+    from_args
+        .or_else(|| {
+            std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(|dir| dir.join("assets").join("tileset.ron")))
+                .filter(|path| path.exists())
+        })
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_TILESET_PATH))
+}
+
 fn main() {
+    let tileset = load_tileset_description(&tileset_path_from_args()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(options) = batch_options_from_args(&tileset) {
+        let result = options.and_then(|options| run_batch(&options, &tileset));
+        match result {
+            Ok(0) => return,
+            Ok(failed) => eprintln!("{} maps failed", failed),
+            Err(e) => eprintln!("{}", e)
+        }
+        std::process::exit(1);
+    }
+    if let Some(options) = render_options_from_args() {
+        if let Err(e) = options.and_then(|options| run_render(&options, &tileset)) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     orom_miniquad::start(conf::Conf {
         window_resizable: false,
         window_width: SCREEN_WIDTH,
@@ -632,8 +1154,8 @@ fn main() {
         high_dpi: true,
         sample_count: 0,
         ..Default::default()
-    }, |mut ctx| {
-        UserData::owning(Stage::new(&mut ctx), ctx)
+    }, move |mut ctx| {
+        UserData::owning(Stage::new(&mut ctx, tileset), ctx)
     });
 }
 
@@ -647,7 +1169,7 @@ impl Stage { // Drawing related stuff
                 {
                     match self.compound_results_receiver.try_recv() {
                         Ok(Ok(r)) => {
-                            self.tile_modules = r;
+                            self.complete_collapse(r);
                             self.iterative_update_state = IterationState::Presenting;
                         }
                         Ok(Err(_)) => {
@@ -660,11 +1182,12 @@ impl Stage { // Drawing related stuff
             }
             let possible_res = self.iterative_results_receiver.try_recv();
             if let Ok((next_idx, next_prop)) = possible_res {
-                let row = next_idx / WIDTH;
-                let column = next_idx % WIDTH;
+                let row = next_idx / self.map_width;
+                let column = next_idx % self.map_width;
                 if get_bits_set_count(&next_prop) == 1 {
-                    let tile_id = next_prop.find_first_set(0).unwrap();
+                    let tile_id = variant_tile(&self.tiles, next_prop.find_first_set(0).unwrap(), row, column, self.seed);
                     let tile_info = &self.tiles[tile_id];
+                    self.minimap.set_tile(next_idx, tile_info);
                     BlitBuilder::try_create(&mut self.surfaces.stage_surface, &self.surfaces.atlas)
                         .expect("failed to create blit builder")
                         .with_source_subrect(tile_info.tile_x, tile_info.tile_y, 32, 32)
@@ -693,11 +1216,13 @@ impl Stage { // Drawing related stuff
                 steps_per_iteration -= 1;
             } else {
                 if self.iterative_update_state == IterationState::Presenting {
+                    // the presented steps may end on a state the collapse backtracked from
+                    self.minimap.update(&self.tiles, &self.tile_modules);
                     self.iterative_update_state = IterationState::Idle;
                 } else if self.iterative_update_state == IterationState::Collapsing {
                     match self.compound_results_receiver.try_recv() {
                         Ok(Ok(r)) => {
-                            self.tile_modules = r;
+                            self.complete_collapse(r);
                             self.iterative_update_state = IterationState::Idle;
                         }
                         Ok(Err(_)) => {
@@ -719,12 +1244,16 @@ impl Stage { // Drawing related stuff
         let tx1 = self.iterative_results_transmitter.clone();
         let tx2 = self.compound_results_transmitter.clone();
         let modules = self.modules.clone();
+        let (width, height) = (self.map_width, self.map_height);
+        let seed = self.seed;
+        let choice_heuristic = self.make_choice_heuristic(tileset);
 
         thread::spawn(move || {
-            let mut wfc_context = WfcContextBuilder::new(&modules, WIDTH, HEIGHT)
-                .with_entropy_choice_heuristic(Box::new(
-                    StrictDrawingChoiceHeuristic { preferable_bits: tileset }
+            let mut wfc_context = WfcContextBuilder::new(&modules, width, height)
+                .with_entropy_heuristic(Box::new(
+                    SeededEntropyHeuristic { rng: make_rng(seed, ENTROPY_STREAM) }
                 ))
+                .with_entropy_choice_heuristic(choice_heuristic)
                 .with_history_transmitter(tx1)
                 .build();
 
@@ -732,59 +1261,255 @@ impl Stage { // Drawing related stuff
         });
     }
 
+    fn make_choice_heuristic(&self, tileset: CustomBitSet) -> Box<dyn WfcEntropyChoiceHeuristic<CustomBitSet> + Send> {
+        make_choice_heuristic(
+            &self.tileset,
+            &self.tiles,
+            self.map_width,
+            self.map_height,
+            tileset,
+            &self.weights,
+            if self.use_heightmap { Some(&self.noise_params) } else { None },
+            self.seed
+        )
+    }
+
     fn collapse(&mut self, ctx: &mut Context, tileset: CustomBitSet) {
-        let mut wfc_context = WfcContextBuilder::new(&self.modules, WIDTH, HEIGHT)
-            .with_entropy_choice_heuristic(Box::new(
-                StrictDrawingChoiceHeuristic { preferable_bits: tileset }
-            ))
-            .build();
-
-        wfc_context.collapse(10, self.compound_results_transmitter.clone());
-        if let Ok(tile_modules) = self.compound_results_receiver.recv().unwrap() {
-            self.tile_modules = tile_modules;
-            for idx in 0..self.tile_modules.len() {
-                let row = idx / WIDTH;
-                let column = idx % WIDTH;
-                let tile_id = self.tile_modules[idx];
-                let tile_info = &self.tiles[tile_id];
-                BlitBuilder::try_create(&mut self.surfaces.stage_surface, &self.surfaces.atlas)
-                    .expect("failed to create blit builder")
-                    .with_source_subrect(tile_info.tile_x, tile_info.tile_y, 32, 32)
-                    .with_dest_pos(column as i32 * 32, row as i32 * 32)
-                    .blit();
+        let tile_modules = generate_tile_modules(
+            &self.tiles,
+            &self.modules,
+            self.map_width,
+            self.map_height,
+            self.make_choice_heuristic(tileset),
+            self.seed
+        );
+        if let Ok(tile_modules) = tile_modules {
+            self.complete_collapse(tile_modules);
+            self.redraw_tilemap(ctx);
+        }
+    }
+
+    /// Takes the result of a full collapse, whichever way it was run
+    fn complete_collapse(&mut self, tile_modules: Vec<usize>) {
+        self.replace_tile_modules(tile_modules);
+        self.minimap.update(&self.tiles, &self.tile_modules);
+        self.rescatter_after_collapse();
+    }
+
+    fn replace_tile_modules(&mut self, mut tile_modules: Vec<usize>) {
+        canonicalize_tiles(&self.tiles, &mut tile_modules);
+        self.history.record(&self.tile_modules, &tile_modules);
+        self.tile_modules = tile_modules;
+        self.walkability_dirty = true;
+    }
+
+    fn blit_tile(&mut self, idx: usize) {
+        let row = idx / self.map_width;
+        let column = idx % self.map_width;
+        let tile_id = variant_tile(&self.tiles, self.tile_modules[idx], row, column, self.seed);
+        let tile_info = &self.tiles[tile_id];
+        self.minimap.set_tile(idx, tile_info);
+        BlitBuilder::try_create(&mut self.surfaces.stage_surface, &self.surfaces.atlas)
+            .expect("failed to create blit builder")
+            .with_source_subrect(tile_info.tile_x, tile_info.tile_y, 32, 32)
+            .with_dest_pos(column as i32 * 32, row as i32 * 32)
+            .blit();
+    }
+
+    fn redraw_tilemap(&mut self, ctx: &mut Context) {
+        for idx in 0..self.tile_modules.len() {
+            self.blit_tile(idx);
+        }
+
+        let casted = bytemuck::cast_slice(self.surfaces.stage_surface.color_data());
+        self.tilemap_bindings.images[0].update(ctx, casted);
+    }
+
+    fn undo(&mut self, ctx: &mut Context) {
+        let touched = self.history.undo(&mut self.tile_modules);
+        self.walkability_dirty |= !touched.is_empty();
+        self.redraw_tiles(ctx, &touched);
+    }
+
+    fn redo(&mut self, ctx: &mut Context) {
+        let touched = self.history.redo(&mut self.tile_modules);
+        self.walkability_dirty |= !touched.is_empty();
+        self.redraw_tiles(ctx, &touched);
+    }
+
+    fn refresh_objects(&mut self, ctx: &mut Context) {
+        let (buffer, errors) = render_objects(
+            &self.object_layer,
+            &mut self.sprite_cache,
+            self.map_width,
+            self.map_height
+        );
+        if let Some(e) = errors.first() {
+            self.status_message = e.clone();
+        }
+        self.tilemap_bindings.images[2].update(ctx, bytemuck::cast_slice(&buffer));
+        self.objects_dirty = false;
+    }
+
+    fn place_object(&mut self) {
+        let (column, row) = self.tile_selection;
+        self.object_layer.place(PlacedObject {
+            row,
+            column,
+            sprite_path: OBJECT_PALETTE[self.selected_object].to_string(),
+            frame: self.object_frame
+        });
+        self.objects_dirty = true;
+    }
+
+    fn scatter(&mut self) {
+        let placed = scatter_objects(
+            &mut self.object_layer,
+            &self.tiles,
+            &self.tileset,
+            &self.tile_modules,
+            self.map_width,
+            self.map_height,
+            DEFAULT_SCATTER_RULES,
+            &self.scatter_settings,
+            self.seed
+        );
+        self.status_message = format!("Scattered {} objects", placed);
+        self.objects_dirty = true;
+    }
+
+    /// Objects of the previous terrain make no sense on a freshly collapsed one
+    fn rescatter_after_collapse(&mut self) {
+        if self.scatter_after_collapse {
+            self.object_layer.clear();
+            self.scatter();
+        }
+    }
+
+    fn remove_object(&mut self) {
+        let (column, row) = self.tile_selection;
+        self.objects_dirty |= self.object_layer.remove_at(row, column);
+    }
+
+    fn refresh_walkability(&mut self, ctx: &mut Context) {
+        let walkability = Walkability::analyze(&self.tiles, &self.tile_modules, self.map_width, self.map_height);
+        let bytes = if self.show_walkability {
+            walkability.overlay_bytes()
+        } else {
+            vec![0u8; self.map_width * self.map_height * 4]
+        };
+        self.tilemap_bindings.images[1].update(ctx, &bytes);
+        self.walkability = Some(walkability);
+        self.walkability_dirty = false;
+    }
+
+    /// Clears the narrowest barriers between isolated regions and the main one.
+    /// Runs again after the queued collapses are drawn until the map is connected
+    fn repair_step(&mut self) {
+        let barriers: Vec<(usize, usize)> = match &self.walkability {
+            Some(walkability) if !walkability.is_connected() => (0..walkability.region_sizes.len())
+                .filter(|&region| Some(region) != walkability.main_region)
+                .filter_map(|region| walkability.barrier_crossing(region, self.map_width, self.map_height))
+                .flatten()
+                .collect(),
+            _ => {
+                self.repair_attempts_left = 0;
+                self.status_message = "Map is fully connected".to_string();
+                return;
             }
+        };
+        self.repair_attempts_left -= 1;
+        if barriers.is_empty() {
+            self.repair_attempts_left = 0;
+            self.status_message = "Failed to connect the map".to_string();
+            return;
+        }
+        if self.repair_attempts_left == 0 {
+            self.status_message = "Map is still not connected, try repairing again".to_string();
+        }
+        self.history.begin_stroke();
+        self.enqueue_cells(&barriers, BASE_TERRAIN);
+    }
+
+    /// Replaces the map with another one of possibly different dimensions
+    fn resize_map(&mut self, ctx: &mut Context, width: usize, height: usize, tile_modules: Vec<usize>) {
+        if width != self.map_width || height != self.map_height {
+            self.map_width = width;
+            self.map_height = height;
+            self.tile_resolution = (width as f32, height as f32);
+            self.tile_selection = (
+                self.tile_selection.0.min(width - 1),
+                self.tile_selection.1.min(height - 1)
+            );
+            self.surfaces.stage_surface = TrueColorSurfaceSprite::new(width * 32, height * 32);
+            let texture = make_surface_texture(ctx, &self.surfaces.stage_surface);
+            let old_texture = std::mem::replace(&mut self.tilemap_bindings.images[0], texture);
+            old_texture.delete();
+            let overlay = make_overlay_texture(ctx, width, height);
+            let old_overlay = std::mem::replace(&mut self.tilemap_bindings.images[1], overlay);
+            old_overlay.delete();
+            let objects = make_overlay_texture(ctx, width * 32, height * 32);
+            let old_objects = std::mem::replace(&mut self.tilemap_bindings.images[2], objects);
+            old_objects.delete();
+            let minimap_texture = make_overlay_texture(ctx, width, height);
+            let old_minimap = std::mem::replace(&mut self.minimap_texture, minimap_texture);
+            old_minimap.delete();
+            self.object_layer.objects.retain(|it| it.row < height && it.column < width);
+            self.objects_dirty = true;
+            self.repair_attempts_left = 0;
+            self.walkability_dirty = true;
+            self.draw_queue.clear();
+            self.history.clear();
+            self.tile_modules = tile_modules;
+            canonicalize_tiles(&self.tiles, &mut self.tile_modules);
+            self.minimap = Minimap::new(&self.tileset, &self.tiles, &self.tile_modules);
+            self.camera.center_on(width as f32 / 2.0, height as f32 / 2.0);
+            self.camera.clamp_to_map(width, height);
+        } else {
+            self.replace_tile_modules(tile_modules);
+        }
+        self.redraw_tilemap(ctx);
+    }
 
-            let casted = bytemuck::cast_slice(self.surfaces.stage_surface.color_data());
-            self.tilemap_bindings.images[0].update(ctx, casted);
+    fn redraw_tiles(&mut self, ctx: &mut Context, indices: &[usize]) {
+        if indices.is_empty() { return; }
+        for &idx in indices {
+            self.blit_tile(idx);
         }
+
+        let casted = bytemuck::cast_slice(self.surfaces.stage_surface.color_data());
+        self.tilemap_bindings.images[0].update(ctx, casted);
     }
 
     fn flush_draw_queue(&mut self, ctx: &mut Context) {
-        while !self.draw_queue.is_empty() {
-            let (next_row, next_column, tool) = self.draw_queue.pop_front().unwrap();
-            let mut preferable_bits = CustomBitSet::empty();
-            let tool_tile = match tool {
-                GRASS => 4,
-                PLATEAU => 28,
-                SAND => 52,
-                SAVANNAH => 76,
-                ROCKS => 100,
-                HIGH_ROCKS => 172,
-                WATER => 196,
-                ROAD => 220,
-                _ => 13
+        // Big brushes and fills queue a lot of collapses, so spread them over several frames
+        for _ in 0..MAX_DRAWS_PER_UPDATE {
+            let (next_row, next_column, tool) = match self.draw_queue.pop_front() {
+                Some(draw) => draw,
+                None => break
             };
-            if tool != LAND {
-                let offset = tool_tile - 4;
-                for ix in 0..24 {
-                    preferable_bits.set(offset + ix);
+            let mut preferable_bits = CustomBitSet::empty();
+            let tool_tile = fill_tile(&self.tiles, tool);
+            if let TileKind::InnerFill(_) = self.tiles[tool_tile].kind {
+                // Prefer the transitions into the tool terrain, so the stroke gets smooth edges
+                let transition_set = self.tiles[tool_tile].transition_set;
+                for (tile_id, tile) in self.tiles.iter().enumerate() {
+                    if tile.transition_set == transition_set {
+                        preferable_bits.set(tile_id);
+                    }
                 }
             }
-            let mut wfc_context = WfcContextBuilder::new(&self.modules, WIDTH, HEIGHT)
+            let mut wfc_context = WfcContextBuilder::new(&self.modules, self.map_width, self.map_height)
                 .use_existing_collapse(&self.tile_modules)
                 .with_entropy_heuristic(Box::new(
                     LeastDistanceHeuristic { row: next_row, column: next_column }
                 ))
+                .with_entropy_choice_heuristic(Box::new(
+                    DrawingChoiceHeuristic {
+                        rng: make_rng(local_seed(self.seed, next_row, next_column), CHOICE_STREAM),
+                        preferable_bits
+                    }
+                ))
                 .build();
 
             wfc_context.local_collapse(
@@ -794,20 +1519,15 @@ impl Stage { // Drawing related stuff
                 self.compound_results_transmitter.clone()
             );
 
-            if let Ok(new_tile_modules) = self.compound_results_receiver.recv().unwrap() {
-                for idx in 0..new_tile_modules.len() {
-                    let row = idx / WIDTH;
-                    let column = idx % WIDTH;
-                    if self.tile_modules[idx] == new_tile_modules[idx] { continue; }
-                    let tile_id = new_tile_modules[idx];
-                    let tile_info = &self.tiles[tile_id];
-                    BlitBuilder::try_create(&mut self.surfaces.stage_surface, &self.surfaces.atlas)
-                        .expect("failed to create blit builder")
-                        .with_source_subrect(tile_info.tile_x, tile_info.tile_y, 32, 32)
-                        .with_dest_pos(column as i32 * 32, row as i32 * 32)
-                        .blit();
+            if let Ok(mut new_tile_modules) = self.compound_results_receiver.recv().unwrap() {
+                canonicalize_tiles(&self.tiles, &mut new_tile_modules);
+                let changed: Vec<usize> = (0..new_tile_modules.len())
+                    .filter(|&idx| self.tile_modules[idx] != new_tile_modules[idx])
+                    .collect();
+                self.replace_tile_modules(new_tile_modules);
+                for idx in changed {
+                    self.blit_tile(idx);
                 }
-                self.tile_modules = new_tile_modules;
             }
         }
 
@@ -815,3 +1535,66 @@ impl Stage { // Drawing related stuff
         self.tilemap_bindings.images[0].update(ctx, casted);
     }
 }
+
+impl Stage { // Map files
+    fn save_map(&mut self) {
+        let map_file = MapFile {
+            width: self.map_width,
+            height: self.map_height,
+            tileset_version: self.tileset.version,
+            available_tiles: self.available_tiles.clone(),
+            seed: self.seed,
+            tile_modules: self.tile_modules.clone(),
+            objects: self.object_layer.objects.clone()
+        };
+        self.status_message = match map_file.save(Path::new(&self.map_path)) {
+            Ok(()) => format!("Saved {}", self.map_path),
+            Err(e) => format!("Failed to save {}: {}", self.map_path, e)
+        };
+    }
+
+    fn load_map(&mut self, ctx: &mut Context) {
+        let map_file = match MapFile::load(Path::new(&self.map_path), self.tileset.version) {
+            Ok(map_file) => map_file,
+            Err(e) => {
+                self.status_message = format!("Failed to load {}: {}", self.map_path, e);
+                return;
+            }
+        };
+        if map_file.tile_modules.iter().any(|&tile_id| tile_id >= self.tiles.len()) {
+            self.status_message = format!("Failed to load {}: unknown tile index", self.map_path);
+            return;
+        }
+        self.available_tiles = map_file.available_tiles;
+        self.seed = map_file.seed;
+        self.resize_map(ctx, map_file.width, map_file.height, map_file.tile_modules);
+        self.object_layer.objects = map_file.objects;
+        self.objects_dirty = true;
+        self.status_message = format!("Loaded {}", self.map_path);
+    }
+
+    fn export_image(&mut self) {
+        let image_path = Path::new(&self.map_path).with_extension("png");
+        let mut rgb = surface_to_rgb(&self.surfaces.stage_surface);
+        let (objects, errors) = render_objects(
+            &self.object_layer,
+            &mut self.sprite_cache,
+            self.map_width,
+            self.map_height
+        );
+        overlay_bgra(&mut rgb, &objects);
+        let thumbnail = if self.export_thumbnail { Some(THUMBNAIL_SIZE) } else { None };
+        let result = save_png_with_thumbnail(
+            &image_path,
+            self.map_width * 32,
+            self.map_height * 32,
+            &rgb,
+            thumbnail
+        );
+        self.status_message = match (result, errors.first()) {
+            (Err(e), _) => format!("Failed to export {}: {}", image_path.display(), e),
+            (Ok(()), Some(e)) => format!("Exported {}, but {}", image_path.display(), e),
+            (Ok(()), None) => format!("Exported {}", image_path.display())
+        };
+    }
+}
@@ -0,0 +1,242 @@
+//! Map file layout, all the numbers are little endian:
+//!
+//! | size         | content                                                      |
+//! |--------------|--------------------------------------------------------------|
+//! | 8            | magic `OROMMAP\0`                                            |
+//! | 2            | format version (`MAP_FILE_VERSION`)                          |
+//! | 2            | tile definitions version (`TilesetDescription::version`)     |
+//! | 4            | map width in tiles                                           |
+//! | 4            | map height in tiles                                          |
+//! | 2            | terrains allowed in generation, bit N stands for terrain N   |
+//! | 8            | generation seed (since version 2)                            |
+//! | 2 * w * h    | indices into `make_tiling_lookup()`, row by row              |
+//! | 4            | count of placed objects (since version 3)                    |
+//!
+//! Every object takes a u16 row, u16 column, u16 sprite frame, u8 length of the sprite
+//! path and the path itself
+
+use {
+    crate::{
+        constants::{MIN_MAP_SIDE, MAX_MAP_SIDE},
+        tiling::AvailableTiles,
+        objects::PlacedObject
+    },
+    std::{
+        fs::File,
+        io::{self, Read, Write, BufReader, BufWriter},
+        path::Path
+    }
+};
+
+pub const MAP_FILE_MAGIC: &[u8; 8] = b"OROMMAP\0";
+pub const MAP_FILE_VERSION: u16 = 3;
+
+pub struct MapFile {
+    pub width: usize,
+    pub height: usize,
+    pub tileset_version: u16,
+    pub available_tiles: AvailableTiles,
+    pub seed: u64,
+    pub tile_modules: Vec<usize>,
+    pub objects: Vec<PlacedObject>
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl MapFile {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: &Path, expected_tileset_version: u16) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?), expected_tileset_version)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAP_FILE_MAGIC)?;
+        writer.write_all(&MAP_FILE_VERSION.to_le_bytes())?;
+        writer.write_all(&self.tileset_version.to_le_bytes())?;
+        writer.write_all(&(self.width as u32).to_le_bytes())?;
+        writer.write_all(&(self.height as u32).to_le_bytes())?;
+        writer.write_all(&self.available_tiles.to_mask().to_le_bytes())?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        for &tile_id in &self.tile_modules {
+            writer.write_all(&(tile_id as u16).to_le_bytes())?;
+        }
+        writer.write_all(&(self.objects.len() as u32).to_le_bytes())?;
+        for object in &self.objects {
+            let path = object.sprite_path.as_bytes();
+            if path.len() > u8::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "sprite path is too long"));
+            }
+            writer.write_all(&(object.row as u16).to_le_bytes())?;
+            writer.write_all(&(object.column as u16).to_le_bytes())?;
+            writer.write_all(&object.frame.to_le_bytes())?;
+            writer.write_all(&[path.len() as u8])?;
+            writer.write_all(path)?;
+        }
+        Ok(())
+    }
+
+    /// Maps refer to tiles by their indices, so only the maps made with
+    /// `expected_tileset_version` of the tileset are accepted
+    pub fn read<R: Read>(reader: &mut R, expected_tileset_version: u16) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAP_FILE_MAGIC {
+            return Err(invalid_data("not a map file".to_string()));
+        }
+        let version = read_u16(reader)?;
+        if version == 0 || version > MAP_FILE_VERSION {
+            return Err(invalid_data(format!("unsupported map file version {}", version)));
+        }
+        let tileset_version = read_u16(reader)?;
+        if tileset_version != expected_tileset_version {
+            return Err(invalid_data(format!(
+                "map was made with tile definitions v{}, but v{} is in use",
+                tileset_version,
+                expected_tileset_version
+            )));
+        }
+        let width = read_u32(reader)? as usize;
+        let height = read_u32(reader)? as usize;
+        let side_range = MIN_MAP_SIDE..=MAX_MAP_SIDE;
+        if !side_range.contains(&width) || !side_range.contains(&height) {
+            return Err(invalid_data(format!(
+                "map is {}x{}, but sides from {} to {} are supported",
+                width, height, MIN_MAP_SIDE, MAX_MAP_SIDE
+            )));
+        }
+        let tile_count = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid_data(format!("map of {}x{} tiles is too large", width, height)))?;
+        let available_tiles = AvailableTiles::from_mask(read_u16(reader)?);
+        let seed = if version >= 2 { read_u64(reader)? } else { 0 };
+        let mut tile_modules = Vec::with_capacity(tile_count);
+        for _ in 0..tile_count {
+            tile_modules.push(read_u16(reader)? as usize);
+        }
+        let mut objects = Vec::new();
+        if version >= 3 {
+            let count = read_u32(reader)? as usize;
+            if count > tile_count {
+                return Err(invalid_data(format!("too many objects: {}", count)));
+            }
+            for _ in 0..count {
+                let row = read_u16(reader)? as usize;
+                let column = read_u16(reader)? as usize;
+                let frame = read_u16(reader)?;
+                let mut path = vec![0u8; read_u8(reader)? as usize];
+                reader.read_exact(&mut path)?;
+                let sprite_path = String::from_utf8(path)
+                    .map_err(|_| invalid_data("sprite path is not valid UTF-8".to_string()))?;
+                if row >= height || column >= width {
+                    return Err(invalid_data(format!("object at {}:{} is out of the map", row, column)));
+                }
+                objects.push(PlacedObject { row, column, sprite_path, frame });
+            }
+        }
+        Ok(Self { width, height, tileset_version, available_tiles, seed, tile_modules, objects })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::io::Cursor
+    };
+
+    const TILESET_VERSION: u16 = 7;
+
+    fn sample_map() -> MapFile {
+        let (width, height) = (MIN_MAP_SIDE, MIN_MAP_SIDE + 3);
+        MapFile {
+            width,
+            height,
+            tileset_version: TILESET_VERSION,
+            available_tiles: AvailableTiles::from_mask(0b1_0110_1011),
+            seed: 0xDEAD_BEEF_0123,
+            tile_modules: (0..width * height).map(|it| it % 200).collect(),
+            objects: vec![PlacedObject {
+                row: 2,
+                column: 5,
+                sprite_path: "objects/tree1".to_string(),
+                frame: 3
+            }]
+        }
+    }
+
+    fn write_to_bytes(map_file: &MapFile) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        map_file.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn saved_map_loads_back() {
+        let map_file = sample_map();
+        let loaded = MapFile::read(&mut Cursor::new(write_to_bytes(&map_file)), TILESET_VERSION).unwrap();
+        assert_eq!((loaded.width, loaded.height), (map_file.width, map_file.height));
+        assert_eq!(loaded.tileset_version, map_file.tileset_version);
+        assert_eq!(loaded.available_tiles.to_mask(), map_file.available_tiles.to_mask());
+        assert_eq!(loaded.seed, map_file.seed);
+        assert_eq!(loaded.tile_modules, map_file.tile_modules);
+        assert_eq!(loaded.objects.len(), 1);
+        let (object, expected) = (&loaded.objects[0], &map_file.objects[0]);
+        assert_eq!((object.row, object.column, object.frame), (expected.row, expected.column, expected.frame));
+        assert_eq!(object.sprite_path, expected.sprite_path);
+    }
+
+    #[test]
+    fn maps_of_another_tileset_are_rejected() {
+        let bytes = write_to_bytes(&sample_map());
+        let error = MapFile::read(&mut Cursor::new(bytes), TILESET_VERSION + 1)
+            .err()
+            .expect("tileset version was not checked");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn out_of_range_sides_are_rejected() {
+        // Width and height follow the magic and both of the versions
+        let size_offset = MAP_FILE_MAGIC.len() + 4;
+        for &(width, height) in &[(0, 16), (16, MAX_MAP_SIDE as u32 + 1), (u32::MAX, u32::MAX)] {
+            let mut bytes = write_to_bytes(&sample_map());
+            bytes[size_offset..size_offset + 4].copy_from_slice(&width.to_le_bytes());
+            bytes[size_offset + 4..size_offset + 8].copy_from_slice(&height.to_le_bytes());
+            let error = MapFile::read(&mut Cursor::new(bytes), TILESET_VERSION)
+                .err()
+                .expect("map size was accepted");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
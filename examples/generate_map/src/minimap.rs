@@ -0,0 +1,80 @@
+use crate::{
+    tiling::TileInfo,
+    tileset::TilesetDescription
+};
+
+/// Longest side of the minimap image in points
+pub const MINIMAP_SIZE: f32 = 192.0;
+
+/// One RGBA texel per tile colored after its dominant terrain
+pub struct Minimap {
+    pub bytes: Vec<u8>,
+    /// Set when some texels changed since the last upload
+    pub dirty: bool,
+    /// Texel of every terrain of the tileset
+    terrain_texels: Vec<[u8; 4]>
+}
+
+impl Minimap {
+    pub fn new(description: &TilesetDescription, tiles: &[TileInfo], tile_modules: &[usize]) -> Self {
+        let terrain_texels: Vec<[u8; 4]> = description.terrains.iter().map(|it| texel(it.color)).collect();
+        let mut bytes = Vec::with_capacity(tile_modules.len() * 4);
+        for &tile_id in tile_modules {
+            bytes.extend_from_slice(&terrain_texels[tiles[tile_id].dominant_terrain() as usize]);
+        }
+        Self { bytes, dirty: true, terrain_texels }
+    }
+
+    /// Recolors the whole map, for when all the tiles were replaced at once
+    pub fn update(&mut self, tiles: &[TileInfo], tile_modules: &[usize]) {
+        for (idx, &tile_id) in tile_modules.iter().enumerate() {
+            self.set_tile(idx, &tiles[tile_id]);
+        }
+    }
+
+    pub fn set_tile(&mut self, idx: usize, tile: &TileInfo) {
+        let texel = self.terrain_texels[tile.dominant_terrain() as usize];
+        if self.bytes[idx * 4..idx * 4 + 4] != texel {
+            self.bytes[idx * 4..idx * 4 + 4].copy_from_slice(&texel);
+            self.dirty = true;
+        }
+    }
+}
+
+fn texel((r, g, b): (f32, f32, f32)) -> [u8; 4] {
+    let channel = |value: f32| (value.max(0.0).min(1.0) * 255.0) as u8;
+    [channel(r), channel(g), channel(b), 0xFF]
+}
+
+/// Size of the minimap image keeping the proportions of the map
+pub fn minimap_extent(map_width: usize, map_height: usize) -> (f32, f32) {
+    let scale = MINIMAP_SIZE / map_width.max(map_height) as f32;
+    (map_width as f32 * scale, map_height as f32 * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{tiling::{make_tiling_lookup, fill_tile}, tileset::tests::stock_tileset}
+    };
+
+    #[test]
+    fn update_recolors_replaced_tiles() {
+        let description = stock_tileset();
+        let tiles = make_tiling_lookup(&description);
+        let water = description.terrain("water").unwrap();
+        let mut tile_modules = vec![fill_tile(&tiles, 0); 6];
+        let mut minimap = Minimap::new(&description, &tiles, &tile_modules);
+        minimap.dirty = false;
+
+        minimap.update(&tiles, &tile_modules);
+        assert!(!minimap.dirty);
+
+        tile_modules[4] = fill_tile(&tiles, water);
+        minimap.update(&tiles, &tile_modules);
+        assert!(minimap.dirty);
+        assert_eq!(minimap.bytes[16..20], texel(description.terrains[water as usize].color));
+        assert_eq!(minimap.bytes[0..4], texel(description.terrains[0].color));
+    }
+}
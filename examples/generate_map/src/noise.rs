@@ -0,0 +1,97 @@
+use serde::Deserialize;
+
+/// Parts of the height range split by the levels of `NoiseParams`
+#[derive(Deserialize, Copy, Clone, PartialEq)]
+pub enum HeightBand {
+    Low,
+    Middle,
+    High,
+    Peak
+}
+
+/// Parameters of a fractal value noise heightmap and of the way heights turn into terrains
+#[derive(Clone)]
+pub struct NoiseParams {
+    /// Size of the largest features in tiles
+    pub scale: f32,
+    pub octaves: u32,
+    /// Amplitude multiplier applied to every next octave
+    pub persistence: f32,
+    pub water_level: f32,
+    pub rocks_level: f32,
+    pub high_rocks_level: f32
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self {
+            scale: 16.0,
+            octaves: 4,
+            persistence: 0.5,
+            water_level: 0.35,
+            rocks_level: 0.65,
+            high_rocks_level: 0.8
+        }
+    }
+}
+
+impl NoiseParams {
+    /// Terrains of the tileset name the band they look natural in
+    pub fn band_at(&self, height: f32) -> HeightBand {
+        if height < self.water_level {
+            HeightBand::Low
+        } else if height < self.rocks_level {
+            HeightBand::Middle
+        } else if height < self.high_rocks_level {
+            HeightBand::High
+        } else {
+            HeightBand::Peak
+        }
+    }
+}
+
+fn lattice_value(x: i64, y: i64, octave: u32, seed: u64) -> f32 {
+    let mut hash = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (octave as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    hash ^= hash >> 33;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn smooth_value_noise(x: f32, y: f32, octave: u32, seed: u64) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (sx, sy) = (fx * fx * (3.0 - 2.0 * fx), fy * fy * (3.0 - 2.0 * fy));
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    let top = lattice_value(ix, iy, octave, seed) * (1.0 - sx) + lattice_value(ix + 1, iy, octave, seed) * sx;
+    let bottom = lattice_value(ix, iy + 1, octave, seed) * (1.0 - sx) + lattice_value(ix + 1, iy + 1, octave, seed) * sx;
+    top * (1.0 - sy) + bottom * sy
+}
+
+/// Heights in 0..1 for every tile of the map, row by row
+pub fn make_heightmap(width: usize, height: usize, params: &NoiseParams, seed: u64) -> Vec<f32> {
+    let octaves = params.octaves.max(1);
+    let mut heights = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for column in 0..width {
+            let (mut amplitude, mut frequency) = (1.0, 1.0 / params.scale.max(1.0));
+            let (mut sum, mut norm) = (0.0, 0.0);
+            for octave in 0..octaves {
+                sum += amplitude * smooth_value_noise(
+                    column as f32 * frequency,
+                    row as f32 * frequency,
+                    octave,
+                    seed
+                );
+                norm += amplitude;
+                amplitude *= params.persistence;
+                frequency *= 2.0;
+            }
+            heights.push(sum / norm);
+        }
+    }
+    heights
+}
@@ -0,0 +1,186 @@
+use {
+    crate::constants::GRAPHICS_RES,
+    rom_res_rs::ResourceFile,
+    rom_loaders_rs::images::ingame_sprite::{read_image, read_palette, ImageType},
+    rom_media_rs::image_rendering::{
+        bmp_sprite_decorators::TrueColorSurfaceSprite,
+        blittable::BlitBuilder,
+        ingame_sprite_decorators::PalettedSpriteRenderingScope
+    },
+    std::{collections::HashMap, io::Cursor}
+};
+
+/// Sprites offered in the object palette, the paths are the ones used by the atlas baker
+pub const OBJECT_PALETTE: &[&str] = &[
+    "objects/oak1/sprites.256",
+    "objects/oak2/sprites.256",
+    "objects/oak3/sprites.256",
+    "objects/pine1/sprites.256",
+    "objects/pine2/sprites.256",
+    "objects/pine3/sprites.256",
+    "objects/maple1/sprites.256",
+    "objects/maple2/sprites.256",
+    "objects/elka1/sprites.256",
+    "objects/elka2/sprites.256",
+    "objects/iva1/sprites.256",
+    "objects/bush1/sprites.256",
+    "objects/bush2/sprites.256",
+    "objects/bush3/sprites.256",
+    "objects/stones/sprites.256",
+    "objects/statue/sprites.256",
+    "objects/totem/sprites.256",
+    "objects/fence/sprites.256",
+    "structures/hut1/house.256",
+    "structures/hut2/house.256",
+    "structures/inn1/house.256",
+    "structures/church/house.256",
+    "structures/castle/house.256",
+    "structures/cave/house.256",
+    "structures/campfire/house.256"
+];
+
+#[derive(Clone)]
+pub struct PlacedObject {
+    pub row: usize,
+    pub column: usize,
+    pub sprite_path: String,
+    pub frame: u16
+}
+
+/// Objects standing on the map, at most one per tile
+pub struct ObjectLayer {
+    pub objects: Vec<PlacedObject>
+}
+
+impl Default for ObjectLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ObjectLayer {
+    pub fn new() -> Self {
+        Self { objects: Vec::new() }
+    }
+
+    /// Places an object replacing the one which stood on the same tile
+    pub fn place(&mut self, object: PlacedObject) {
+        self.remove_at(object.row, object.column);
+        self.objects.push(object);
+    }
+
+    pub fn remove_at(&mut self, row: usize, column: usize) -> bool {
+        let count = self.objects.len();
+        self.objects.retain(|it| it.row != row || it.column != column);
+        self.objects.len() != count
+    }
+
+    pub fn clear(&mut self) {
+        self.objects.clear();
+    }
+}
+
+/// Frames of object sprites rendered into surfaces, loaded from GRAPHICS.RES on first use
+pub struct SpriteCache {
+    sprites: HashMap<String, Result<Vec<TrueColorSurfaceSprite>, String>>
+}
+
+impl Default for SpriteCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpriteCache {
+    pub fn new() -> Self {
+        Self { sprites: HashMap::new() }
+    }
+
+    pub fn frames(&mut self, sprite_path: &str) -> Result<&[TrueColorSurfaceSprite], String> {
+        self.sprites
+            .entry(sprite_path.to_string())
+            .or_insert_with(|| load_sprite_frames(sprite_path))
+            .as_ref()
+            .map(|it| it.as_slice())
+            .map_err(|e| e.clone())
+    }
+
+    pub fn frame_count(&mut self, sprite_path: &str) -> usize {
+        self.frames(sprite_path).map(|it| it.len()).unwrap_or(0)
+    }
+}
+
+fn load_sprite_frames(sprite_path: &str) -> Result<Vec<TrueColorSurfaceSprite>, String> {
+    let mut resource_file = ResourceFile::new(Cursor::new(GRAPHICS_RES))
+        .map_err(|e| format!("failed to open GRAPHICS.RES: {:?}", e))?;
+    let bytes = resource_file
+        .get_resource_bytes(sprite_path)
+        .map_err(|e| format!("failed to load resource {}: {:?}", sprite_path, e))?;
+    let image = read_image(&mut Cursor::new(&bytes[..]), ImageType::Dot256)
+        .map_err(|e| format!("failed to read {}: {:?}", sprite_path, e))?;
+    let palette = read_palette(&mut Cursor::new(&bytes[..]), ImageType::Dot256)
+        .map_err(|e| format!("failed to read palette of {}: {:?}", sprite_path, e))?
+        .ok_or_else(|| format!("{} has no palette", sprite_path))?;
+
+    let mut frames = Vec::with_capacity(image.frames.len());
+    for img_id in 0..image.frames.len() {
+        let frame = &image.frames[img_id];
+        let mut surface = TrueColorSurfaceSprite::new(frame.width as usize, frame.height as usize);
+        let scope = &PalettedSpriteRenderingScope {
+            image_data: &image,
+            palette: &palette,
+            img_id
+        };
+        BlitBuilder::try_create(&mut surface, scope)
+            .map_err(|e| format!("failed to render {}: {:?}", sprite_path, e))?
+            .blit();
+        frames.push(surface);
+    }
+    Ok(frames)
+}
+
+/// Renders the objects into a BGRA buffer covering the whole map. Sprites stand on the bottom
+/// of their tile, centered horizontally, and the ones closer to the bottom of the map go on top.
+/// Objects which failed to load are returned as errors and skipped
+pub fn render_objects(
+    layer: &ObjectLayer,
+    cache: &mut SpriteCache,
+    map_width: usize,
+    map_height: usize
+) -> (Vec<u32>, Vec<String>) {
+    let (buffer_width, buffer_height) = (map_width * 32, map_height * 32);
+    let mut buffer = vec![0u32; buffer_width * buffer_height];
+    let mut errors = Vec::new();
+
+    let mut order: Vec<&PlacedObject> = layer.objects.iter().collect();
+    order.sort_by_key(|it| (it.row, it.column));
+    for object in order {
+        let frames = match cache.frames(&object.sprite_path) {
+            Ok(frames) => frames,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        let sprite = match frames.get(object.frame as usize) {
+            Some(sprite) => sprite,
+            None => continue
+        };
+        let (sprite_width, sprite_height) = (sprite.get_width(), sprite.get_height());
+        let left = (object.column * 32 + 16) as i64 - sprite_width as i64 / 2;
+        let top = (object.row * 32 + 32) as i64 - sprite_height as i64;
+        let colors = sprite.color_data();
+        for y in 0..sprite_height {
+            let dest_y = top + y as i64;
+            if dest_y < 0 || dest_y >= buffer_height as i64 { continue; }
+            for x in 0..sprite_width {
+                let dest_x = left + x as i64;
+                if dest_x < 0 || dest_x >= buffer_width as i64 { continue; }
+                let color = colors[y * sprite_width + x];
+                if color == 0 { continue; }
+                buffer[dest_y as usize * buffer_width + dest_x as usize] = color | 0xFF00_0000;
+            }
+        }
+    }
+    (buffer, errors)
+}
@@ -0,0 +1,137 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap
+};
+
+/// Finds the cheapest 4-connected route between two cells (row, column) with A*.
+/// `cost` gives the price of stepping onto a cell and should be at least 1,
+/// cells which cost `u32::MAX` are never entered
+pub fn find_path<F>(
+    width: usize,
+    height: usize,
+    start: (usize, usize),
+    goal: (usize, usize),
+    cost: F
+) -> Option<Vec<(usize, usize)>>
+    where F: Fn(usize, usize) -> u32
+{
+    let distance = |(row, column): (usize, usize)| {
+        ((row as i64 - goal.0 as i64).abs() + (column as i64 - goal.1 as i64).abs()) as u32
+    };
+    let start_idx = start.0 * width + start.1;
+    let mut best_cost = vec![u32::MAX; width * height];
+    let mut came_from = vec![usize::MAX; width * height];
+    let mut open = BinaryHeap::new();
+    best_cost[start_idx] = 0;
+    open.push(Reverse((distance(start), start_idx)));
+
+    while let Some(Reverse((_, idx))) = open.pop() {
+        let (row, column) = (idx / width, idx % width);
+        if (row, column) == goal {
+            let mut path = vec![goal];
+            let mut current = idx;
+            while current != start_idx {
+                current = came_from[current];
+                path.push((current / width, current % width));
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let neighbours = [
+            (row.wrapping_sub(1), column),
+            (row + 1, column),
+            (row, column.wrapping_sub(1)),
+            (row, column + 1)
+        ];
+        for &(next_row, next_column) in &neighbours {
+            if next_row >= height || next_column >= width { continue; }
+            let next_idx = next_row * width + next_column;
+            let next_cost = best_cost[idx].saturating_add(cost(next_row, next_column).max(1));
+            if next_cost < best_cost[next_idx] {
+                best_cost[next_idx] = next_cost;
+                came_from[next_idx] = idx;
+                open.push(Reverse((next_cost + distance((next_row, next_column)), next_idx)));
+            }
+        }
+    }
+    None
+}
+
+/// Routes a path through all the waypoints in order, joining the legs without repeating cells
+pub fn route_through<F>(
+    width: usize,
+    height: usize,
+    waypoints: &[(usize, usize)],
+    cost: F
+) -> Option<Vec<(usize, usize)>>
+    where F: Fn(usize, usize) -> u32
+{
+    let mut route: Vec<(usize, usize)> = waypoints.first().copied().into_iter().collect();
+    for leg in waypoints.windows(2) {
+        let path = find_path(width, height, leg[0], leg[1], &cost)?;
+        route.extend(path.into_iter().skip(1));
+    }
+    Some(route)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_cost<F: Fn(usize, usize) -> u32>(path: &[(usize, usize)], cost: F) -> u32 {
+        path.iter().skip(1).map(|&(row, column)| cost(row, column)).sum()
+    }
+
+    fn assert_connected(path: &[(usize, usize)]) {
+        for step in path.windows(2) {
+            let (a, b) = (step[0], step[1]);
+            let distance = (a.0 as i64 - b.0 as i64).abs() + (a.1 as i64 - b.1 as i64).abs();
+            assert_eq!(distance, 1, "{:?} and {:?} are not neighbours", a, b);
+        }
+    }
+
+    #[test]
+    fn path_on_flat_ground_is_as_short_as_possible() {
+        let path = find_path(8, 6, (1, 1), (4, 6), |_, _| 1).unwrap();
+        assert_eq!(path.first(), Some(&(1, 1)));
+        assert_eq!(path.last(), Some(&(4, 6)));
+        assert_eq!(path.len(), 3 + 5 + 1);
+        assert_connected(&path);
+    }
+
+    #[test]
+    fn path_goes_around_expensive_cells() {
+        // a costly wall in column 2 with a cheap gap at the bottom row
+        let cost = |row: usize, column: usize| if column == 2 && row < 4 { 20 } else { 1 };
+        let path = find_path(5, 5, (0, 0), (0, 4), cost).unwrap();
+        assert_connected(&path);
+        assert!(path.contains(&(4, 2)), "path {:?} crosses the wall", path);
+        assert_eq!(total_cost(&path, cost), 12);
+    }
+
+    #[test]
+    fn walled_off_goal_has_no_path() {
+        let cost = |_: usize, column: usize| if column == 3 { u32::MAX } else { 1 };
+        assert_eq!(find_path(6, 4, (2, 0), (2, 5), cost), None);
+        assert_eq!(route_through(6, 4, &[(0, 0), (3, 1), (1, 5)], cost), None);
+    }
+
+    #[test]
+    fn route_visits_waypoints_in_order() {
+        let waypoints = [(0, 0), (5, 2), (1, 6), (6, 6)];
+        let route = route_through(8, 8, &waypoints, |_, _| 1).unwrap();
+        assert_connected(&route);
+        let mut visited = route.iter();
+        for waypoint in &waypoints {
+            assert!(visited.any(|cell| cell == waypoint), "{:?} is missed or out of order", waypoint);
+        }
+        assert_eq!(route.last(), Some(&(6, 6)));
+        assert_eq!(route.len(), (5 + 2) + (4 + 4) + 5 + 1);
+    }
+
+    #[test]
+    fn short_waypoint_lists() {
+        assert_eq!(route_through(4, 4, &[], |_, _| 1), Some(vec![]));
+        assert_eq!(route_through(4, 4, &[(2, 3)], |_, _| 1), Some(vec![(2, 3)]));
+    }
+}
@@ -6,84 +6,80 @@ use {
         blittable::BlitBuilder
     },
     orom_miniquad::{Texture, TextureParams, TextureFormat, TextureWrap, FilterMode, Context},
-    crate::constants::{GRAPHICS_RES, GUI_TEXTURE_BYTES, INFO_TEXT_BYTES},
+    crate::{
+        constants::{GRAPHICS_RES, TERRAIN_ICONS, INFO_TEXT_BYTES},
+        tileset::{TilesetDescription, ATLAS_SIDE, SHEET_COLUMN_HEIGHT}
+    },
     std::io::Cursor
 };
 
-pub fn load_atlas_texture() -> TrueColorSurfaceSprite {
-    let atlas = {
-        let mut resource_file = ResourceFile::new(Cursor::new(GRAPHICS_RES))
-            .expect(&format!("failed to open VIDEO4.RES"));
-        let mut graphics_resources = Vec::new();
-        for j in 1..=2 {
-            for i in 0..16 {
-                let terrain_tile_name = format!("terrain/tile{}-{:#02}.bmp", j, i);
-                let mut resource = Cursor::new(resource_file
-                    .get_resource_bytes(&terrain_tile_name)
-                    .expect(&format!("failed to load resource {}", &terrain_tile_name))
-                );
-                let sprite = BmpSprite::read_from(&mut resource).unwrap();
-                graphics_resources.push(sprite);
-            }
-        }
-        for j in 3..=4 {
-            for i in 0..4 {
-                let terrain_tile_name = format!("terrain/tile{}-{:#02}.bmp", j, i);
-                let mut resource = Cursor::new(resource_file
-                    .get_resource_bytes(&terrain_tile_name)
-                    .expect(&format!("failed to load resource {}", &terrain_tile_name))
-                );
-                let sprite = BmpSprite::read_from(&mut resource).unwrap();
-                graphics_resources.push(sprite);
-            }
-        }
-        let mut atlas = TrueColorSurfaceSprite::new(1024, 1024);
-        for i in 0..graphics_resources.len() {
-            let x = 32 * (i % 16);
-            let y = 6 * 32 * (i / 16);
-
-            BlitBuilder::try_create(&mut atlas, &graphics_resources[i])
+/// Packs the terrain sheets listed in the tileset into an atlas of 32x32 tiles,
+/// the transition sets of the tileset refer to the positions of tiles in it
+pub fn load_atlas_texture(description: &TilesetDescription) -> TrueColorSurfaceSprite {
+    let mut resource_file = ResourceFile::new(Cursor::new(GRAPHICS_RES))
+        .expect("failed to open GRAPHICS.RES");
+    let mut atlas = TrueColorSurfaceSprite::new(ATLAS_SIDE * 32, ATLAS_SIDE * 32);
+    for sheet in &description.atlas {
+        for i in 0..sheet.count {
+            let terrain_tile_name = format!("{}-{:02}.bmp", sheet.sheet, i);
+            let mut resource = Cursor::new(resource_file
+                .get_resource_bytes(&terrain_tile_name)
+                .unwrap_or_else(|_| panic!("failed to load resource {}", &terrain_tile_name))
+            );
+            let sprite = BmpSprite::read_from(&mut resource).unwrap();
+            BlitBuilder::try_create(&mut atlas, &sprite)
                 .expect("failed to create blit builder")
-                .with_source_subrect(0, 0, 32, 6 * 32)
-                .with_dest_pos(x as i32, y as i32)
+                .with_source_subrect(0, 0, 32, SHEET_COLUMN_HEIGHT * 32)
+                .with_dest_pos(((sheet.x + i) * 32) as i32, (sheet.y * 32) as i32)
                 .blit();
         }
-        atlas
-    };
+    }
     atlas
 }
 
-pub fn load_gui_textures(ctx: &mut Context) -> Vec<Texture> {
-    GUI_TEXTURE_BYTES
+/// Side of the flat icon shown for terrains without a stock one
+const PLAIN_ICON_SIDE: usize = 24;
+
+/// Brush icon of every terrain of the tileset: the stock icon of its name when there is one,
+/// otherwise a square of the terrain color
+pub fn load_gui_textures(ctx: &mut Context, description: &TilesetDescription) -> Vec<Texture> {
+    description.terrains
         .iter()
-        .map(|it| {
-            let mut cursor = Cursor::new(*it);
-            let sprite = BmpSprite::read_from(&mut cursor).unwrap();
-            match sprite {
-                BmpSprite::TrueColor { width, height, colors } => {
-                    let casted = bytemuck::cast_slice(&colors);
-                    let mut bytes = Vec::with_capacity(width*height);
-                    for offset in 0..width*height {
-                        let offset = offset * 4;
-                        bytes.push(casted[offset + 2]);
-                        bytes.push(casted[offset + 1]);
-                        bytes.push(casted[offset]);
-                        bytes.push(casted[offset + 3]);
-                    }
-                    Texture::from_data_and_format(
-                        ctx,
-                        &bytes,
-                        TextureParams {
-                            format: TextureFormat::RGBA8,
-                            wrap: TextureWrap::Clamp,
-                            filter: FilterMode::Linear,
-                            width: width as u32,
-                            height: height as u32
+        .map(|terrain| {
+            let (width, height, bytes) = match TERRAIN_ICONS.iter().find(|(name, _)| *name == terrain.name) {
+                Some((_, icon)) => match BmpSprite::read_from(&mut Cursor::new(*icon)).unwrap() {
+                    BmpSprite::TrueColor { width, height, colors } => {
+                        let casted = bytemuck::cast_slice(&colors);
+                        let mut bytes = Vec::with_capacity(width*height);
+                        for offset in 0..width*height {
+                            let offset = offset * 4;
+                            bytes.push(casted[offset + 2]);
+                            bytes.push(casted[offset + 1]);
+                            bytes.push(casted[offset]);
+                            bytes.push(casted[offset + 3]);
                         }
-                    )
+                        (width, height, bytes)
+                    }
+                    _ => unreachable!()
+                },
+                None => {
+                    let (r, g, b) = terrain.color;
+                    let channel = |value: f32| (value.max(0.0).min(1.0) * 255.0) as u8;
+                    let texel = [channel(r), channel(g), channel(b), 0xFF];
+                    (PLAIN_ICON_SIDE, PLAIN_ICON_SIDE, texel.repeat(PLAIN_ICON_SIDE * PLAIN_ICON_SIDE))
                 }
-                _ => unreachable!()
-            }
+            };
+            Texture::from_data_and_format(
+                ctx,
+                &bytes,
+                TextureParams {
+                    format: TextureFormat::RGBA8,
+                    wrap: TextureWrap::Clamp,
+                    filter: FilterMode::Linear,
+                    width: width as u32,
+                    height: height as u32
+                }
+            )
         }).collect()
 }
 
@@ -0,0 +1,231 @@
+use {
+    crate::{
+        tiling::TileInfo,
+        tileset::TilesetDescription,
+        objects::{ObjectLayer, PlacedObject}
+    },
+    rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom}
+};
+
+/// Which sprites may grow on tiles fully covered with a terrain, and how often
+pub struct ScatterRule {
+    /// Name of the terrain as listed in the tileset description
+    pub terrain: &'static str,
+    pub sprites: &'static [&'static str],
+    /// Share of free tiles which get an object at the global density of 1
+    pub density: f32
+}
+
+pub const DEFAULT_SCATTER_RULES: &[ScatterRule] = &[
+    ScatterRule {
+        terrain: "grass",
+        sprites: &[
+            "objects/pine1/sprites.256",
+            "objects/pine2/sprites.256",
+            "objects/oak1/sprites.256",
+            "objects/oak2/sprites.256",
+            "objects/maple1/sprites.256"
+        ],
+        density: 0.6
+    },
+    ScatterRule {
+        terrain: "savannah",
+        sprites: &[
+            "objects/bush1/sprites.256",
+            "objects/bush2/sprites.256",
+            "objects/bush3/sprites.256"
+        ],
+        density: 0.35
+    },
+    ScatterRule {
+        terrain: "land",
+        sprites: &["objects/bush3/sprites.256"],
+        density: 0.05
+    },
+    ScatterRule {
+        terrain: "rocks",
+        sprites: &["objects/stones/sprites.256"],
+        density: 0.3
+    },
+    ScatterRule {
+        terrain: "plateau",
+        sprites: &["objects/stones/sprites.256"],
+        density: 0.1
+    }
+];
+
+#[derive(Clone)]
+pub struct ScatterSettings {
+    /// Multiplier of the rule densities
+    pub density: f32,
+    /// Least distance between two objects in tiles
+    pub min_distance: f32
+}
+
+impl Default for ScatterSettings {
+    fn default() -> Self {
+        Self { density: 1.0, min_distance: 1.5 }
+    }
+}
+
+/// Terrain of a tile which is fully covered with it. Transition tiles give `None`
+fn solid_terrain(tile: &TileInfo) -> Option<u8> {
+    let corners = [tile.north_west, tile.north_east, tile.south_west, tile.south_east];
+    if corners.iter().all(|&it| it == corners[0]) {
+        Some(corners[0])
+    } else {
+        None
+    }
+}
+
+/// Scatters objects over free tiles of the layer. Candidates are visited in a seeded random
+/// order and rejected when another object stands closer than `min_distance`, which gives
+/// a Poisson-disk like spacing. Transition and impassable tiles and terrains without a rule never get anything
+pub fn scatter_objects(
+    layer: &mut ObjectLayer,
+    tiles: &[TileInfo],
+    description: &TilesetDescription,
+    tile_modules: &[usize],
+    width: usize,
+    height: usize,
+    rules: &[ScatterRule],
+    settings: &ScatterSettings,
+    seed: u64
+) -> usize {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut occupied = vec![false; width * height];
+    for object in &layer.objects {
+        occupied[object.row * width + object.column] = true;
+    }
+
+    let mut candidates: Vec<usize> = (0..width * height).collect();
+    candidates.shuffle(&mut rng);
+
+    let reach = settings.min_distance.max(0.0).ceil() as i64;
+    let min_distance_squared = settings.min_distance * settings.min_distance;
+    let mut placed = 0;
+    for idx in candidates {
+        let tile = &tiles[tile_modules[idx]];
+        let terrain = match solid_terrain(tile) {
+            Some(terrain) if tile.passable => &description.terrains[terrain as usize].name,
+            _ => continue
+        };
+        let rule = match rules.iter().find(|it| it.terrain == terrain) {
+            Some(rule) if !rule.sprites.is_empty() => rule,
+            _ => continue
+        };
+        if occupied[idx] || rng.gen::<f32>() >= rule.density * settings.density {
+            continue;
+        }
+
+        let (row, column) = ((idx / width) as i64, (idx % width) as i64);
+        let mut too_close = false;
+        'search: for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let (other_row, other_column) = (row + dy, column + dx);
+                if other_row < 0 || other_column < 0 || other_row >= height as i64 || other_column >= width as i64 {
+                    continue;
+                }
+                if occupied[other_row as usize * width + other_column as usize] &&
+                    ((dx * dx + dy * dy) as f32) < min_distance_squared
+                {
+                    too_close = true;
+                    break 'search;
+                }
+            }
+        }
+        if too_close { continue; }
+
+        occupied[idx] = true;
+        layer.place(PlacedObject {
+            row: row as usize,
+            column: column as usize,
+            sprite_path: rule.sprites[rng.gen_range(0, rule.sprites.len())].to_string(),
+            frame: 0
+        });
+        placed += 1;
+    }
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{tiling::{make_tiling_lookup, fill_tile}, tileset::tests::stock_tileset}
+    };
+
+    const TEST_RULES: &[ScatterRule] = &[
+        ScatterRule { terrain: "grass", sprites: &["objects/pine1/sprites.256"], density: 1.0 },
+        ScatterRule { terrain: "water", sprites: &["objects/stones/sprites.256"], density: 1.0 }
+    ];
+
+    /// Grass on the left half of the map, water on the right one, land in the last row
+    fn make_map(width: usize, height: usize) -> (TilesetDescription, Vec<TileInfo>, Vec<usize>) {
+        let description = stock_tileset();
+        let tiles = make_tiling_lookup(&description);
+        let terrain = |name| description.terrain(name).unwrap();
+        let tile_modules = (0..width * height)
+            .map(|idx| fill_tile(&tiles, match (idx / width, idx % width) {
+                (row, _) if row == height - 1 => terrain("land"),
+                (_, column) if column < width / 2 => terrain("grass"),
+                _ => terrain("water")
+            }))
+            .collect();
+        (description, tiles, tile_modules)
+    }
+
+    fn scatter(settings: &ScatterSettings, seed: u64) -> Vec<(usize, usize)> {
+        let (width, height) = (16, 12);
+        let (description, tiles, tile_modules) = make_map(width, height);
+        let mut layer = ObjectLayer::new();
+        let placed = scatter_objects(
+            &mut layer,
+            &tiles,
+            &description,
+            &tile_modules,
+            width,
+            height,
+            TEST_RULES,
+            settings,
+            seed
+        );
+        assert_eq!(placed, layer.objects.len());
+        layer.objects.iter().map(|it| (it.row, it.column)).collect()
+    }
+
+    #[test]
+    fn same_seed_scatters_the_same_objects() {
+        let settings = ScatterSettings::default();
+        let objects = scatter(&settings, 9);
+        assert!(!objects.is_empty());
+        assert_eq!(objects, scatter(&settings, 9));
+        assert_ne!(objects, scatter(&settings, 10));
+    }
+
+    #[test]
+    fn zero_density_places_nothing() {
+        let settings = ScatterSettings { density: 0.0, ..ScatterSettings::default() };
+        assert!(scatter(&settings, 9).is_empty());
+    }
+
+    #[test]
+    fn objects_stay_on_passable_tiles_with_a_rule() {
+        let objects = scatter(&ScatterSettings::default(), 9);
+        // water is impassable and land has no rule
+        assert!(objects.iter().all(|&(row, column)| row < 11 && column < 8), "got {:?}", objects);
+    }
+
+    #[test]
+    fn objects_keep_the_least_distance() {
+        let settings = ScatterSettings { density: 1.0, min_distance: 2.5 };
+        let objects = scatter(&settings, 9);
+        assert!(objects.len() > 4);
+        for (i, a) in objects.iter().enumerate() {
+            for b in &objects[i + 1..] {
+                let (dy, dx) = (a.0 as f32 - b.0 as f32, a.1 as f32 - b.1 as f32);
+                assert!(dx * dx + dy * dy >= 2.5 * 2.5, "{:?} and {:?} are too close", a, b);
+            }
+        }
+    }
+}
@@ -17,27 +17,52 @@ pub const TILEMAP_FRAGMENT: &str = //language=glsl
     varying lowp vec2 texcoord;
 
     uniform sampler2D tex;
+    uniform sampler2D overlay;
+    uniform sampler2D objects;
 
-    uniform lowp vec2 mouse_pos;
-    uniform lowp vec2 tile_resolution;
+    uniform mediump vec2 mouse_pos;
+    uniform mediump vec2 tile_resolution;
 
     uniform lowp vec3 grid_color;
     uniform lowp vec3 tool_color;
 
+    uniform mediump vec2 view_offset;
+    uniform mediump vec2 view_scale;
+
+    uniform mediump float brush_radius;
+    uniform mediump float brush_shape;
+    uniform mediump vec2 rect_start;
+
     void main() {
-        lowp vec2 uv = texcoord * tile_resolution - vec2(0.5);
+        mediump vec2 map_uv = view_offset + texcoord * view_scale;
+        mediump vec2 uv = map_uv * tile_resolution - vec2(0.5);
         lowp vec2 grid_lines = smoothstep(
             vec2(0.05),
             vec2(-0.05),
             fract(uv + vec2(0.5)) - vec2(0.05)
         );
-        lowp float dist = max(abs(uv.x - mouse_pos.x), abs(uv.y - mouse_pos.y));
+        mediump vec2 cell = floor(uv + vec2(0.5));
+        mediump vec2 delta = cell - mouse_pos;
+        // 0 - square brush, 1 - circle brush, 2 - rectangle between rect_start and the cursor
+        lowp float in_square = step(max(abs(delta.x), abs(delta.y)), brush_radius + 0.01);
+        lowp float in_circle = step(dot(delta, delta), brush_radius * (brush_radius + 1.0) + 0.01);
+        lowp vec2 in_rect = step(min(rect_start, mouse_pos) - vec2(0.01), cell) *
+            step(cell, max(rect_start, mouse_pos) + vec2(0.01));
+        lowp float in_brush =
+            in_square * (1.0 - step(0.5, brush_shape)) +
+            in_circle * step(0.5, brush_shape) * (1.0 - step(1.5, brush_shape)) +
+            in_rect.x * in_rect.y * step(1.5, brush_shape);
+        lowp vec4 object_color = texture2D(objects, map_uv);
+        lowp vec3 map_color = mix(texture2D(tex, map_uv).zyx, object_color.zyx, object_color.w);
+        lowp vec4 overlay_color = texture2D(overlay, map_uv);
         lowp vec3 color =
-            texture2D(tex, texcoord).zyx +
+            mix(map_color, overlay_color.xyz, overlay_color.w * 0.5) +
             grid_color * max(grid_lines.x, grid_lines.y) * 0.3 +
-            tool_color * step(dist, 0.5);
+            tool_color * in_brush;
 
-        gl_FragColor = vec4(clamp(color, vec3(0.0), vec3(1.0)), 1.0);
+        lowp vec2 inside = step(vec2(0.0), map_uv) * step(map_uv, vec2(1.0));
+
+        gl_FragColor = vec4(clamp(color, vec3(0.0), vec3(1.0)) * inside.x * inside.y, 1.0);
     }"#;
 
 pub const TEXT_RENDER_VERTEX: &str = //language=glsl
@@ -70,13 +95,18 @@ pub const TEXT_RENDER_FRAGMENT: &str = //language=glsl
 
 pub fn tilemap_meta() -> ShaderMeta {
     ShaderMeta {
-        images: vec!["tex".to_string()],
+        images: vec!["tex".to_string(), "overlay".to_string(), "objects".to_string()],
         uniforms: UniformBlockLayout {
             uniforms: vec![
                 UniformDesc::new("mouse_pos", UniformType::Float2),
                 UniformDesc::new("tile_resolution", UniformType::Float2),
                 UniformDesc::new("grid_color", UniformType::Float3),
                 UniformDesc::new("tool_color", UniformType::Float3),
+                UniformDesc::new("view_offset", UniformType::Float2),
+                UniformDesc::new("view_scale", UniformType::Float2),
+                UniformDesc::new("brush_radius", UniformType::Float1),
+                UniformDesc::new("brush_shape", UniformType::Float1),
+                UniformDesc::new("rect_start", UniformType::Float2),
             ],
         },
     }
@@ -88,6 +118,11 @@ pub struct TilemapUniforms {
     pub tile_resolution: (f32, f32),
     pub grid_color: (f32, f32, f32),
     pub tool_color: (f32, f32, f32),
+    pub view_offset: (f32, f32),
+    pub view_scale: (f32, f32),
+    pub brush_radius: f32,
+    pub brush_shape: f32,
+    pub rect_start: (f32, f32),
 }
 
 pub fn info_text_meta() -> ShaderMeta {
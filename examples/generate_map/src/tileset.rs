@@ -0,0 +1,220 @@
+use {
+    crate::{constants::BASE_TERRAIN, noise::HeightBand, CustomBitSet},
+    serde::Deserialize,
+    std::{fs, path::Path}
+};
+
+/// Amount of tiles which fit into `CustomBitSet`
+const MAX_TILE_COUNT: usize = std::mem::size_of::<CustomBitSet>() * 8;
+/// Map files keep the terrains allowed in generation as bits of a u16
+const MAX_TERRAIN_COUNT: usize = 16;
+/// Side of the tile atlas in 32x32 tiles
+pub const ATLAS_SIDE: usize = 32;
+/// Every image of a sheet is a column of this many tiles
+pub const SHEET_COLUMN_HEIGHT: usize = 6;
+
+#[derive(Deserialize)]
+pub struct TerrainDescription {
+    pub name: String,
+    /// Shown in the brush list and the generation settings
+    pub label: String,
+    /// Color of the brush label, the brush cursor and the minimap
+    pub color: (f32, f32, f32),
+    /// Heightmap band where the terrain looks natural, terrains without one are not preferred
+    #[serde(default)]
+    pub height: Option<HeightBand>,
+    /// Tiles with at least half of the corners covered by such terrains can not be walked over
+    #[serde(default)]
+    pub impassable: bool
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq)]
+pub enum Corner {
+    Outer,
+    Inner
+}
+
+#[derive(Deserialize)]
+pub struct TransitionSetDescription {
+    pub outer: String,
+    pub inner: String,
+    pub x: usize,
+    pub y: usize
+}
+
+/// Images `<sheet>-00.bmp` up to `<sheet>-<count - 1>.bmp` of `GRAPHICS.RES` put side by side
+/// into the atlas, the first one with its top left tile at `x`, `y`
+#[derive(Deserialize)]
+pub struct AtlasSheetDescription {
+    pub sheet: String,
+    pub count: usize,
+    pub x: usize,
+    pub y: usize
+}
+
+impl AtlasSheetDescription {
+    fn covers(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.count && y >= self.y && y < self.y + SHEET_COLUMN_HEIGHT
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LayoutTile {
+    pub x: usize,
+    pub y: usize,
+    /// North west, north east, south west and south east corners
+    pub corners: (Corner, Corner, Corner, Corner),
+    #[serde(default)]
+    pub variant: bool
+}
+
+impl LayoutTile {
+    pub fn corner_list(&self) -> [Corner; 4] {
+        [self.corners.0, self.corners.1, self.corners.2, self.corners.3]
+    }
+
+    pub fn is_filled_with(&self, corner: Corner) -> bool {
+        self.corner_list().iter().all(|&it| it == corner)
+    }
+}
+
+/// Contents of `assets/tileset.ron`, see the comment on top of it for the format
+#[derive(Deserialize)]
+pub struct TilesetDescription {
+    pub version: u16,
+    pub terrains: Vec<TerrainDescription>,
+    pub atlas: Vec<AtlasSheetDescription>,
+    pub transition_sets: Vec<TransitionSetDescription>,
+    pub layout: Vec<LayoutTile>
+}
+
+impl TilesetDescription {
+    /// Parses and validates a tileset. Terrain ids are their places in the list
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let description: Self = ron::de::from_str(source).map_err(|e| format!("{}", e))?;
+        let terrain_count = description.terrains.len();
+        if terrain_count == 0 || terrain_count > MAX_TERRAIN_COUNT {
+            return Err(format!("from 1 to {} terrains are supported, got {}", MAX_TERRAIN_COUNT, terrain_count));
+        }
+        for (terrain, it) in description.terrains.iter().enumerate() {
+            if description.terrain(&it.name)? != terrain as u8 {
+                return Err(format!("terrain {} is listed twice", it.name));
+            }
+        }
+        // new maps are filled with the base terrain and isolated areas get connected with it
+        if description.terrains[BASE_TERRAIN as usize].impassable {
+            let name = &description.terrains[BASE_TERRAIN as usize].name;
+            return Err(format!("base terrain {} should be passable", name));
+        }
+        for sheet in &description.atlas {
+            if sheet.count == 0 || sheet.x + sheet.count > ATLAS_SIDE || sheet.y + SHEET_COLUMN_HEIGHT > ATLAS_SIDE {
+                return Err(format!("sheet {} does not fit into the atlas", sheet.sheet));
+            }
+        }
+        for set in &description.transition_sets {
+            description.terrain(&set.outer)?;
+            description.terrain(&set.inner)?;
+            for tile in &description.layout {
+                let (x, y) = (set.x + tile.x, set.y + tile.y);
+                if !description.atlas.iter().any(|sheet| sheet.covers(x, y)) {
+                    return Err(format!(
+                        "tile {}, {} of the {} to {} set is not covered by any atlas sheet",
+                        x, y, set.outer, set.inner
+                    ));
+                }
+            }
+        }
+        let tile_count = description.transition_sets.len() * description.layout.len();
+        if tile_count > MAX_TILE_COUNT {
+            return Err(format!("{} tiles given, at most {} are supported", tile_count, MAX_TILE_COUNT));
+        }
+        let plain_fills = |corner: Corner| description.layout
+            .iter()
+            .filter(|it| !it.variant && it.is_filled_with(corner))
+            .count();
+        if plain_fills(Corner::Inner) != 1 || plain_fills(Corner::Outer) != 1 {
+            return Err("layout should have exactly one inner and one outer fill tile".to_string());
+        }
+        if description.layout.iter().any(|it| it.variant && !it.is_filled_with(Corner::Inner)) {
+            return Err("variants should be filled with the inner terrain".to_string());
+        }
+        let has_fill = |terrain: &str| description.transition_sets
+            .iter()
+            .any(|it| it.outer == terrain || it.inner == terrain);
+        if let Some(it) = description.terrains.iter().find(|it| !has_fill(&it.name)) {
+            return Err(format!("terrain {} is not used by any transition set", it.name));
+        }
+        Ok(description)
+    }
+
+    pub fn terrain(&self, name: &str) -> Result<u8, String> {
+        self.terrains
+            .iter()
+            .position(|it| it.name == name)
+            .map(|it| it as u8)
+            .ok_or_else(|| format!("unknown terrain {}", name))
+    }
+
+    pub fn terrain_color(&self, terrain: u8) -> (f32, f32, f32) {
+        self.terrains[terrain as usize].color
+    }
+}
+
+/// Reads and parses a tileset, the editor does it once on start
+pub fn load_tileset_description(path: &Path) -> Result<TilesetDescription, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    TilesetDescription::parse(&source).map_err(|e| format!("failed to parse {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub const STOCK_TILESET: &str = include_str!("../../assets/tileset.ron");
+
+    /// The tileset shipped with the editor
+    pub fn stock_tileset() -> TilesetDescription {
+        TilesetDescription::parse(STOCK_TILESET).unwrap()
+    }
+
+    /// Error of parsing the stock tileset with one piece of it replaced
+    fn parse_error(from: &str, to: &str) -> String {
+        assert!(STOCK_TILESET.contains(from), "{} is not in the stock tileset", from);
+        TilesetDescription::parse(&STOCK_TILESET.replacen(from, to, 1))
+            .err()
+            .expect("broken tileset was accepted")
+    }
+
+    #[test]
+    fn stock_tileset_is_valid() {
+        let description = stock_tileset();
+        assert_eq!(description.terrains[BASE_TERRAIN as usize].name, "land");
+        assert!(description.terrains.len() <= MAX_TERRAIN_COUNT);
+        assert!(description.transition_sets.len() * description.layout.len() <= MAX_TILE_COUNT);
+        assert_eq!(description.terrain("water"), Ok(7));
+        assert!(description.terrain("lava").is_err());
+    }
+
+    #[test]
+    fn duplicate_terrains_are_rejected() {
+        let e = parse_error("(name: \"road\"", "(name: \"grass\"");
+        assert!(e.contains("listed twice"), "{}", e);
+    }
+
+    #[test]
+    fn tiles_outside_of_the_atlas_are_rejected() {
+        let e = parse_error("inner: \"road\", x: 4, y: 12", "inner: \"road\", x: 8, y: 12");
+        assert!(e.contains("not covered"), "{}", e);
+        let e = parse_error("inner: \"road\", x: 4, y: 12", "inner: \"road\", x: 30, y: 12");
+        assert!(e.contains("not covered"), "{}", e);
+        let e = parse_error("count: 4, x: 4, y: 12", "count: 4, x: 30, y: 12");
+        assert!(e.contains("does not fit"), "{}", e);
+    }
+
+    #[test]
+    fn tiles_need_four_corners() {
+        parse_error("corners: (Outer, Outer, Outer, Inner)", "corners: (Outer, Outer, Inner)");
+        parse_error("corners: (Outer, Outer, Outer, Inner)", "corners: (Outer, Outer, Outer, Inner, Inner)");
+    }
+}
@@ -1,255 +1,245 @@
 use {
-    crate::{constants::*, CustomBitSet},
+    crate::{
+        tileset::{Corner, TilesetDescription},
+        CustomBitSet
+    },
     simple_tiled_wfc::grid_generation::WfcModule,
     bitsetium::{BitEmpty, BitSet}
 };
 
+/// Terrains allowed in generation, bit N stands for terrain N
+#[derive(Clone)]
 pub struct AvailableTiles {
-    pub land: bool,
-    pub grass: bool,
-    pub plateau: bool,
-    pub sand: bool,
-    pub savannah: bool,
-    pub rocks: bool,
-    pub high_rocks: bool,
-    pub water: bool,
-    pub road: bool,
+    mask: u16
 }
 
 impl Default for AvailableTiles {
     fn default() -> Self {
-        Self {
-            land: true,
-            grass: true,
-            plateau: true,
-            sand: true,
-            savannah: true,
-            rocks: true,
-            high_rocks: true,
-            water: true,
-            road: true,
-        }
+        Self { mask: u16::MAX }
     }
 }
 
 impl AvailableTiles {
-    pub fn make_bitset(&self) -> CustomBitSet {
+    pub fn to_mask(&self) -> u16 {
+        self.mask
+    }
+
+    pub fn from_mask(mask: u16) -> Self {
+        Self { mask }
+    }
+
+    pub fn set(&mut self, terrain: u8, allowed: bool) {
+        if allowed {
+            self.mask |= 1 << terrain;
+        } else {
+            self.mask &= !(1 << terrain);
+        }
+    }
+
+    pub fn contains(&self, terrain: u8) -> bool {
+        self.mask & (1 << terrain) != 0
+    }
+
+    /// Tiles allowed in generation. Transitions need both terrains of their set,
+    /// fill tiles only the terrain they are filled with
+    pub fn make_bitset(&self, tiles: &[TileInfo]) -> CustomBitSet {
         let mut bitset = CustomBitSet::empty();
-        macro_rules! cases {
-            ($($inner:ident, $outer:ident => $offset:expr;)*) => {
-                $(
-                    if self.$inner {
-                        if self.$outer {
-                            for i in 0..18 { bitset.set($offset+i); }
-                        } else {
-                            bitset.set($offset+4);
-                        }
-                        for i in 18..24 { bitset.set($offset+i);}
-                    }else if self.$outer {
-                        bitset.set($offset+13);
-                    }
-                )*
+        for (tile_id, tile) in tiles.iter().enumerate() {
+            let allowed = match tile.kind {
+                TileKind::Transition => self.contains(tile.outer) && self.contains(tile.inner),
+                TileKind::InnerFill(_) => self.contains(tile.inner),
+                TileKind::OuterFill => self.contains(tile.outer),
+                TileKind::FillVariant(_) => false
+            };
+            if allowed {
+                bitset.set(tile_id);
             }
         }
-        cases!(
-            grass, land => 0;
-            plateau, land => 24;
-            sand, land => 24*2;
-            savannah, land => 24*3;
-            rocks, land => 24*4;
-            plateau, rocks => 24*5;
-            grass, savannah => 24*6;
-            high_rocks, rocks => 24*7;
-            water, land => 24*8;
-            road, land => 24*9;
-        );
         bitset
     }
 }
 
+/// Folds a fill variant into the plain inner fill tile of its set. Variants share adjacency
+/// with that tile, so the map structure keeps only the plain one and looks are picked
+/// by `variant_tile` at drawing time
+pub fn canonical_tile(tiles: &[TileInfo], tile_id: usize) -> usize {
+    match tiles[tile_id].kind {
+        TileKind::FillVariant(fill_tile) => fill_tile,
+        _ => tile_id
+    }
+}
+
+pub fn canonicalize_tiles(tiles: &[TileInfo], tile_modules: &mut [usize]) {
+    for tile_id in tile_modules.iter_mut() {
+        *tile_id = canonical_tile(tiles, *tile_id);
+    }
+}
+
+fn position_hash(seed: u64, row: usize, column: usize) -> u64 {
+    let mut hash = seed ^ ((row as u64) << 32 | column as u64);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+/// Tile to draw at the position. An inner fill tile turns into itself or one of its variants
+/// depending only on the position and the seed, so redrawing never reshuffles them
+pub fn variant_tile(tiles: &[TileInfo], tile_id: usize, row: usize, column: usize, seed: u64) -> usize {
+    let tile_id = canonical_tile(tiles, tile_id);
+    let variants = match &tiles[tile_id].kind {
+        TileKind::InnerFill(variants) if !variants.is_empty() => variants,
+        _ => return tile_id
+    };
+    match (position_hash(seed, row, column) % (variants.len() as u64 + 1)) as usize {
+        0 => tile_id,
+        variant => variants[variant - 1]
+    }
+}
+
+/// Tiles to draw for the whole map, see `variant_tile`
+pub fn resolve_variants(tiles: &[TileInfo], tile_modules: &[usize], width: usize, seed: u64) -> Vec<usize> {
+    tile_modules
+        .iter()
+        .enumerate()
+        .map(|(idx, &tile_id)| variant_tile(tiles, tile_id, idx / width, idx % width, seed))
+        .collect()
+}
+
+/// Tile fully covered with the terrain: the inner fill of the first set blending into it,
+/// or the outer fill of the first set starting from it
+pub fn fill_tile(tiles: &[TileInfo], terrain: u8) -> usize {
+    tiles
+        .iter()
+        .position(|it| matches!(it.kind, TileKind::InnerFill(_)) && it.inner == terrain)
+        .or_else(|| tiles.iter().position(|it| it.kind == TileKind::OuterFill && it.outer == terrain))
+        .expect("every terrain has a fill tile")
+}
+
+/// Relative weights used to pick among the tiles left in a slot
+#[derive(Clone, Default)]
+pub struct TerrainWeights {
+    /// Terrains and sets without a weight here weigh 1
+    pub terrains: Vec<f32>,
+    pub transition_sets: Vec<f32>
+}
+
+impl TerrainWeights {
+    /// Every terrain and transition set of the tileset weighs 1
+    pub fn new(description: &TilesetDescription) -> Self {
+        Self {
+            terrains: vec![1.0; description.terrains.len()],
+            transition_sets: vec![1.0; description.transition_sets.len()]
+        }
+    }
+
+    /// Weight of every tile in the lookup: the weight of its transition set
+    /// times the mean weight of the terrains in its corners
+    pub fn tile_weights(&self, tiles: &[TileInfo]) -> Vec<f32> {
+        tiles
+            .iter()
+            .map(|tile| {
+                let corners = [tile.north_west, tile.north_east, tile.south_west, tile.south_east];
+                let terrain_weight = corners
+                    .iter()
+                    .map(|&terrain| self.terrains.get(terrain as usize).copied().unwrap_or(1.0))
+                    .sum::<f32>() / 4.0;
+                let set_weight = self.transition_sets.get(tile.transition_set).copied().unwrap_or(1.0);
+                set_weight * terrain_weight
+            })
+            .collect()
+    }
+}
+
+#[derive(PartialEq)]
+pub enum TileKind {
+    Transition,
+    /// Fully covered with the inner terrain of the set, holds the fill variants of the tile
+    InnerFill(Vec<usize>),
+    OuterFill,
+    /// Another look of the inner fill tile with the given index
+    FillVariant(usize)
+}
+
 pub struct TileInfo {
     pub north_west: u8,
     pub north_east: u8,
     pub south_west: u8,
     pub south_east: u8,
     pub tile_x: usize,
-    pub tile_y: usize
+    pub tile_y: usize,
+    pub transition_set: usize,
+    pub outer: u8,
+    pub inner: u8,
+    pub kind: TileKind,
+    /// False when impassable terrains take at least half of the corners
+    pub passable: bool
 }
 
-pub fn make_tiling_lookup() -> Vec<TileInfo> {
-    let tile_definitions = &[
-        (LAND, GRASS, 0, 0),
-        (LAND, PLATEAU, 4, 0),
-        (LAND, SAND, 8, 0),
-        (LAND, SAVANNAH, 12, 0),
-        (LAND, ROCKS, 0, 6),
-        (ROCKS, PLATEAU, 4, 6),
-        (SAVANNAH, GRASS, 8, 6),
-        (ROCKS, HIGH_ROCKS, 12, 6),
-        (LAND, WATER, 0, 12),
-        (LAND, ROAD, 4, 12)
-    ];
-
-    let mut tiles = Vec::new();
-    for &(outer_type, inner_type, start_tile_x, start_tile_y) in tile_definitions {
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: outer_type,
-            south_west: outer_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 0) * 32,
-            tile_y: (start_tile_y + 0) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: outer_type,
-            south_west: inner_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 1) * 32,
-            tile_y: (start_tile_y + 0) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: outer_type,
-            south_west: inner_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 2) * 32,
-            tile_y: (start_tile_y + 0) * 32
-        });
-
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: inner_type,
-            south_west: outer_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 0) * 32,
-            tile_y: (start_tile_y + 1) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: inner_type,
-            south_west: inner_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 1) * 32,
-            tile_y: (start_tile_y + 1) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: outer_type,
-            south_west: inner_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 2) * 32,
-            tile_y: (start_tile_y + 1) * 32
-        });
-
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: inner_type,
-            south_west: outer_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 0) * 32,
-            tile_y: (start_tile_y + 2) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: inner_type,
-            south_west: outer_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 1) * 32,
-            tile_y: (start_tile_y + 2) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: outer_type,
-            south_west: outer_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 2) * 32,
-            tile_y: (start_tile_y + 2) * 32
-        });
-
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: inner_type,
-            south_west: inner_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 0) * 32,
-            tile_y: (start_tile_y + 3) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: inner_type,
-            south_west: outer_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 1) * 32,
-            tile_y: (start_tile_y + 3) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: inner_type,
-            south_west: outer_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 2) * 32,
-            tile_y: (start_tile_y + 3) * 32
-        });
-
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: outer_type,
-            south_west: inner_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 0) * 32,
-            tile_y: (start_tile_y + 4) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: outer_type,
-            south_west: outer_type,
-            south_east: outer_type,
-            tile_x: (start_tile_x + 1) * 32,
-            tile_y: (start_tile_y + 4) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: inner_type,
-            south_west: outer_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 2) * 32,
-            tile_y: (start_tile_y + 4) * 32
-        });
+impl TileInfo {
+    /// Terrain which covers most of the tile corners. In a tie the north west corner wins
+    pub fn dominant_terrain(&self) -> u8 {
+        let corners = [self.north_west, self.north_east, self.south_west, self.south_east];
+        let count = |terrain: u8| corners.iter().filter(|&&it| it == terrain).count();
+        let mut dominant = corners[0];
+        for &terrain in &corners[1..] {
+            if count(terrain) > count(dominant) {
+                dominant = terrain;
+            }
+        }
+        dominant
+    }
+}
 
-        tiles.push(TileInfo {
-            north_west: inner_type,
-            north_east: outer_type,
-            south_west: inner_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 0) * 32,
-            tile_y: (start_tile_y + 5) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: outer_type,
-            south_west: inner_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 1) * 32,
-            tile_y: (start_tile_y + 5) * 32
-        });
-        tiles.push(TileInfo {
-            north_west: outer_type,
-            north_east: inner_type,
-            south_west: inner_type,
-            south_east: inner_type,
-            tile_x: (start_tile_x + 2) * 32,
-            tile_y: (start_tile_y + 5) * 32
-        });
+/// Builds the tiles of the tileset, see `assets/tileset.ron`
+pub fn make_tiling_lookup(description: &TilesetDescription) -> Vec<TileInfo> {
+    let layout = &description.layout;
+    let inner_fill = layout.iter().position(|it| !it.variant && it.is_filled_with(Corner::Inner)).unwrap();
+    let outer_fill = layout.iter().position(|it| !it.variant && it.is_filled_with(Corner::Outer)).unwrap();
 
-        for j in 0..6 {
+    let mut tiles = Vec::with_capacity(description.transition_sets.len() * layout.len());
+    for (set_id, set) in description.transition_sets.iter().enumerate() {
+        let outer = description.terrain(&set.outer).unwrap();
+        let inner = description.terrain(&set.inner).unwrap();
+        let first_tile = set_id * layout.len();
+        let variants: Vec<usize> = (0..layout.len())
+            .filter(|&it| layout[it].variant)
+            .map(|it| first_tile + it)
+            .collect();
+        for (place, layout_tile) in layout.iter().enumerate() {
+            let [north_west, north_east, south_west, south_east] = {
+                let mut terrains = [outer; 4];
+                for (terrain, &corner) in terrains.iter_mut().zip(layout_tile.corner_list().iter()) {
+                    if corner == Corner::Inner {
+                        *terrain = inner;
+                    }
+                }
+                terrains
+            };
+            let kind = if layout_tile.variant {
+                TileKind::FillVariant(first_tile + inner_fill)
+            } else if place == inner_fill {
+                TileKind::InnerFill(variants.clone())
+            } else if place == outer_fill {
+                TileKind::OuterFill
+            } else {
+                TileKind::Transition
+            };
+            let impassable_corners = [north_west, north_east, south_west, south_east]
+                .iter()
+                .filter(|&&terrain| description.terrains[terrain as usize].impassable)
+                .count();
             tiles.push(TileInfo {
-                north_west: inner_type,
-                north_east: inner_type,
-                south_west: inner_type,
-                south_east: inner_type,
-                tile_x: (start_tile_x + 3) * 32,
-                tile_y: (start_tile_y + j) * 32
+                north_west,
+                north_east,
+                south_west,
+                south_east,
+                tile_x: (set.x + layout_tile.x) * 32,
+                tile_y: (set.y + layout_tile.y) * 32,
+                transition_set: set_id,
+                outer,
+                inner,
+                kind,
+                passable: impassable_corners < 2
             });
         }
     }
@@ -284,4 +274,70 @@ pub fn make_module_set(tiles: &[TileInfo]) -> Vec<WfcModule<[u8; 30]>> {
         modules
     };
     modules
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::tileset::tests::stock_tileset};
+
+    fn grass_fill(tiles: &[TileInfo]) -> (usize, Vec<usize>) {
+        let grass = fill_tile(tiles, stock_tileset().terrain("grass").unwrap());
+        match &tiles[grass].kind {
+            TileKind::InnerFill(variants) if !variants.is_empty() => (grass, variants.clone()),
+            _ => panic!("grass fill has no variants")
+        }
+    }
+
+    #[test]
+    fn position_hash_depends_on_every_input() {
+        assert_eq!(position_hash(5, 3, 7), position_hash(5, 3, 7));
+        assert_ne!(position_hash(5, 3, 7), position_hash(6, 3, 7));
+        assert_ne!(position_hash(5, 3, 7), position_hash(5, 7, 3));
+        assert_ne!(position_hash(5, 3, 7), position_hash(5, 3, 8));
+    }
+
+    #[test]
+    fn variant_is_stable_and_stays_within_the_fill() {
+        let tiles = make_tiling_lookup(&stock_tileset());
+        let (grass, variants) = grass_fill(&tiles);
+        let mut used = vec![false; variants.len() + 1];
+        for row in 0..16 {
+            for column in 0..16 {
+                let tile_id = variant_tile(&tiles, grass, row, column, 11);
+                assert_eq!(tile_id, variant_tile(&tiles, grass, row, column, 11));
+                match variants.iter().position(|&it| it == tile_id) {
+                    Some(variant) => used[variant + 1] = true,
+                    None => {
+                        assert_eq!(tile_id, grass);
+                        used[0] = true;
+                    }
+                }
+            }
+        }
+        assert!(used.iter().all(|&it| it), "some looks never show up: {:?}", used);
+    }
+
+    #[test]
+    fn variants_are_resolved_from_the_plain_fill() {
+        let tiles = make_tiling_lookup(&stock_tileset());
+        let (grass, variants) = grass_fill(&tiles);
+        for &variant in &variants {
+            assert_eq!(canonical_tile(&tiles, variant), grass);
+            assert_eq!(variant_tile(&tiles, variant, 2, 9, 11), variant_tile(&tiles, grass, 2, 9, 11));
+        }
+        let transition = tiles.iter().position(|it| it.kind == TileKind::Transition).unwrap();
+        assert_eq!(variant_tile(&tiles, transition, 2, 9, 11), transition);
+    }
+
+    #[test]
+    fn map_resolution_matches_single_tiles() {
+        let tiles = make_tiling_lookup(&stock_tileset());
+        let (grass, _) = grass_fill(&tiles);
+        let (width, height) = (7, 5);
+        let tile_modules = vec![grass; width * height];
+        let resolved = resolve_variants(&tiles, &tile_modules, width, 11);
+        for (idx, &tile_id) in resolved.iter().enumerate() {
+            assert_eq!(tile_id, variant_tile(&tiles, grass, idx / width, idx % width, 11));
+        }
+        assert_ne!(resolved, resolve_variants(&tiles, &tile_modules, width, 12));
+    }
+}
@@ -0,0 +1,221 @@
+use {
+    crate::tiling::TileInfo,
+    std::{
+        cmp::Reverse,
+        collections::{BinaryHeap, VecDeque}
+    }
+};
+
+/// Price of stepping onto an impassable tile while looking for a way through a barrier
+const BARRIER_COST: u32 = 8;
+
+/// Passable tiles split into 4-connected regions
+pub struct Walkability {
+    /// Region of every tile, `None` for impassable ones
+    pub regions: Vec<Option<usize>>,
+    pub region_sizes: Vec<usize>,
+    /// The largest region, every other one is considered isolated
+    pub main_region: Option<usize>
+}
+
+impl Walkability {
+    pub fn analyze(tiles: &[TileInfo], tile_modules: &[usize], width: usize, height: usize) -> Self {
+        let passable: Vec<bool> = tile_modules.iter().map(|&it| tiles[it].passable).collect();
+        let mut regions = vec![None; width * height];
+        let mut region_sizes = Vec::new();
+        let mut queue = VecDeque::new();
+        for start in 0..width * height {
+            if !passable[start] || regions[start].is_some() { continue; }
+            let region = region_sizes.len();
+            let mut size = 0;
+            regions[start] = Some(region);
+            queue.push_back(start);
+            while let Some(idx) = queue.pop_front() {
+                size += 1;
+                for next_idx in neighbours(idx, width, height) {
+                    if passable[next_idx] && regions[next_idx].is_none() {
+                        regions[next_idx] = Some(region);
+                        queue.push_back(next_idx);
+                    }
+                }
+            }
+            region_sizes.push(size);
+        }
+        let main_region = (0..region_sizes.len()).max_by_key(|&region| region_sizes[region]);
+        Self { regions, region_sizes, main_region }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.region_sizes.len() <= 1
+    }
+
+    pub fn isolated_tile_count(&self) -> usize {
+        self.regions
+            .iter()
+            .filter(|it| it.is_some() && **it != self.main_region)
+            .count()
+    }
+
+    /// One RGBA texel per tile: isolated tiles are tinted red, the rest is transparent
+    pub fn overlay_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.regions.len() * 4);
+        for region in &self.regions {
+            if region.is_some() && *region != self.main_region {
+                bytes.extend_from_slice(&[0xFF, 0x20, 0x20, 0xFF]);
+            } else {
+                bytes.extend_from_slice(&[0, 0, 0, 0]);
+            }
+        }
+        bytes
+    }
+
+    /// Cheapest way from the given region into the main one. Returns impassable cells
+    /// (row, column) along it, clearing them joins the regions
+    pub fn barrier_crossing(&self, region: usize, width: usize, height: usize) -> Option<Vec<(usize, usize)>> {
+        let main_region = self.main_region?;
+        let mut best_cost = vec![u32::MAX; width * height];
+        let mut came_from = vec![usize::MAX; width * height];
+        let mut open = BinaryHeap::new();
+        for idx in 0..self.regions.len() {
+            if self.regions[idx] == Some(region) {
+                best_cost[idx] = 0;
+                open.push(Reverse((0, idx)));
+            }
+        }
+        while let Some(Reverse((cost, idx))) = open.pop() {
+            if cost > best_cost[idx] { continue; }
+            if self.regions[idx] == Some(main_region) {
+                let mut barrier = Vec::new();
+                let mut current = idx;
+                while best_cost[current] != 0 {
+                    if self.regions[current].is_none() {
+                        barrier.push((current / width, current % width));
+                    }
+                    current = came_from[current];
+                }
+                return Some(barrier);
+            }
+            for next_idx in neighbours(idx, width, height) {
+                let step = if self.regions[next_idx].is_some() { 1 } else { BARRIER_COST };
+                let next_cost = cost + step;
+                if next_cost < best_cost[next_idx] {
+                    best_cost[next_idx] = next_cost;
+                    came_from[next_idx] = idx;
+                    open.push(Reverse((next_cost, next_idx)));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn neighbours(idx: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (row, column) = (idx / width, idx % width);
+    let candidates = [
+        (row.wrapping_sub(1), column),
+        (row + 1, column),
+        (row, column.wrapping_sub(1)),
+        (row, column + 1)
+    ];
+    candidates
+        .to_vec()
+        .into_iter()
+        .filter(move |&(row, column)| row < height && column < width)
+        .map(move |(row, column)| row * width + column)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{tiling::{make_tiling_lookup, fill_tile}, tileset::tests::stock_tileset}
+    };
+
+    /// Builds a map of fill tiles from rows of `.` for land, `~` for water and `^` for high rocks
+    fn make_map(tiles: &[TileInfo], rows: &[&str]) -> (Vec<usize>, usize, usize) {
+        let description = stock_tileset();
+        let terrain = |name| description.terrain(name).unwrap();
+        let tile_modules = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|cell| fill_tile(tiles, match cell {
+                '~' => terrain("water"),
+                '^' => terrain("high_rocks"),
+                _ => terrain("land")
+            }))
+            .collect();
+        (tile_modules, rows[0].len(), rows.len())
+    }
+
+    #[test]
+    fn tiles_are_impassable_when_half_of_the_corners_are() {
+        let description = stock_tileset();
+        let tiles = make_tiling_lookup(&description);
+        let (land, water, rocks) = (
+            description.terrain("land").unwrap(),
+            description.terrain("water").unwrap(),
+            description.terrain("high_rocks").unwrap()
+        );
+        assert!(tiles[fill_tile(&tiles, land)].passable);
+        assert!(!tiles[fill_tile(&tiles, water)].passable);
+        assert!(!tiles[fill_tile(&tiles, rocks)].passable);
+        let shores: Vec<&TileInfo> = tiles.iter().filter(|it| it.outer == land && it.inner == water).collect();
+        assert!(!shores.is_empty());
+        for tile in shores {
+            let water_corners = [tile.north_west, tile.north_east, tile.south_west, tile.south_east]
+                .iter()
+                .filter(|&&terrain| terrain == water)
+                .count();
+            assert_eq!(tile.passable, water_corners < 2, "tile at {}, {}", tile.tile_x, tile.tile_y);
+        }
+    }
+
+    #[test]
+    fn regions_are_split_by_impassable_tiles() {
+        let tiles = make_tiling_lookup(&stock_tileset());
+        let (tile_modules, width, height) = make_map(&tiles, &[
+            "...~..",
+            "...~..",
+            "~~~^..",
+            "......",
+        ]);
+        let walkability = Walkability::analyze(&tiles, &tile_modules, width, height);
+        assert_eq!(walkability.region_sizes.len(), 2);
+        assert!(!walkability.is_connected());
+        assert_eq!(walkability.regions[3], None);
+        assert_eq!(walkability.regions[2 * width + 3], None);
+        let main_region = walkability.main_region.unwrap();
+        assert_eq!(walkability.region_sizes[main_region], 12);
+        assert_eq!(walkability.isolated_tile_count(), 6);
+
+        let overlay = walkability.overlay_bytes();
+        assert_eq!(overlay.len(), width * height * 4);
+        assert_eq!(overlay[3], 0xFF, "isolated corner is not tinted");
+        assert_eq!(overlay[(3 * width) * 4 + 3], 0, "main region is tinted");
+    }
+
+    #[test]
+    fn open_map_is_connected() {
+        let tiles = make_tiling_lookup(&stock_tileset());
+        let (tile_modules, width, height) = make_map(&tiles, &["....", ".^^.", "...."]);
+        let walkability = Walkability::analyze(&tiles, &tile_modules, width, height);
+        assert!(walkability.is_connected());
+        assert_eq!(walkability.isolated_tile_count(), 0);
+    }
+
+    #[test]
+    fn barrier_crossing_takes_the_thinnest_wall() {
+        let tiles = make_tiling_lookup(&stock_tileset());
+        let (tile_modules, width, height) = make_map(&tiles, &[
+            "..~~....",
+            "..~~....",
+            "..~.....",
+            "..~~....",
+        ]);
+        let walkability = Walkability::analyze(&tiles, &tile_modules, width, height);
+        let isolated = walkability.regions[0].unwrap();
+        assert_ne!(Some(isolated), walkability.main_region);
+        let barrier = walkability.barrier_crossing(isolated, width, height).unwrap();
+        assert_eq!(barrier, vec![(2, 2)]);
+    }
+}
@@ -0,0 +1,301 @@
+use {
+    rom_res_rs::ResourceFile,
+    std::{
+        fs,
+        io::Cursor,
+        path::{Path, PathBuf},
+        convert::TryInto
+    }
+};
+
+const RES_SIGNATURE: u32 = 0x31415926;
+const RES_HEADER_SIZE: usize = 24;
+const RES_ENTRY_SIZE: usize = 32;
+const RES_DIRECTORY: u32 = 1;
+/// Deeper nesting means a broken directory table, the game archives have a single level
+const MAX_DIRECTORY_DEPTH: usize = 8;
+
+pub struct VideoInfo {
+    pub archive: String,
+    pub entry: String,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    pub duration_ms: f32
+}
+
+pub struct VideoBrowser {
+    pub assets_dir: PathBuf,
+    pub videos: Vec<VideoInfo>,
+    pub errors: Vec<String>
+}
+
+impl VideoBrowser {
+    /// Opens every VIDEO*.RES archive in the directory and collects info about
+    /// all the `.smk` entries listed in their directory tables
+    pub fn scan(assets_dir: &Path) -> Self {
+        let mut videos = Vec::new();
+        let mut errors = Vec::new();
+
+        let mut archives: Vec<String> = match fs::read_dir(assets_dir) {
+            Ok(dir) => dir
+                .filter_map(|it| it.ok())
+                .filter_map(|it| it.file_name().into_string().ok())
+                .filter(|name| {
+                    let upper = name.to_uppercase();
+                    upper.starts_with("VIDEO") && upper.ends_with(".RES")
+                })
+                .collect(),
+            Err(e) => {
+                errors.push(format!("failed to read {}: {}", assets_dir.display(), e));
+                Vec::new()
+            }
+        };
+        archives.sort();
+
+        for archive in archives {
+            scan_archive(&assets_dir.join(&archive), &archive, &mut videos, &mut errors);
+        }
+
+        Self { assets_dir: assets_dir.to_path_buf(), videos, errors }
+    }
+}
+
+fn scan_archive(
+    path: &Path,
+    archive: &str,
+    videos: &mut Vec<VideoInfo>,
+    errors: &mut Vec<String>
+) {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return errors.push(format!("failed to read {}: {}", path.display(), e))
+    };
+    let entries = match list_entries(&bytes) {
+        Ok(entries) => entries,
+        Err(e) => return errors.push(format!("failed to list {}: {}", archive, e))
+    };
+    let mut cursor = Cursor::new(&bytes[..]);
+    let mut resource_file = match ResourceFile::new(&mut cursor) {
+        Ok(resource_file) => resource_file,
+        Err(e) => return errors.push(format!("failed to open {}: {:?}", archive, e))
+    };
+
+    for entry in entries.into_iter().filter(|it| it.to_lowercase().ends_with(".smk")) {
+        let smk_bytes = match resource_file.get_resource_bytes(&entry) {
+            Ok(smk_bytes) => smk_bytes,
+            Err(e) => {
+                errors.push(format!("failed to load {}:{}: {:?}", archive, entry, e));
+                continue;
+            }
+        };
+        match read_smacker_header(&smk_bytes) {
+            Some((width, height, frames, duration_ms)) => videos.push(VideoInfo {
+                archive: archive.to_string(),
+                entry,
+                width,
+                height,
+                frames,
+                duration_ms
+            }),
+            None => errors.push(format!("{}:{} is not a smacker file", archive, entry))
+        }
+    }
+}
+
+/// Paths of all the files of a RES archive, folders are separated with `/`.
+///
+/// The archive starts with six u32 values: signature, index of the first root entry,
+/// count of root entries, flags, offset of the entry table and its size. Every entry takes
+/// 32 bytes: a reserved u32, offset and size of the data, type (1 for folders) and
+/// a zero padded name of 16 bytes. Data of a folder is the index of its first child
+/// entry and the count of its children
+pub fn list_entries(bytes: &[u8]) -> Result<Vec<String>, String> {
+    if read_u32(bytes, 0) != Some(RES_SIGNATURE) {
+        return Err("not a RES archive".to_string());
+    }
+    let header = |offset: usize| read_u32(bytes, offset)
+        .map(|it| it as usize)
+        .ok_or_else(|| "truncated header".to_string());
+    let (root_first, root_count, table_offset) = (header(4)?, header(8)?, header(16)?);
+    if table_offset < RES_HEADER_SIZE {
+        return Err("entry table overlaps the header".to_string());
+    }
+    let mut paths = Vec::new();
+    list_folder(bytes, table_offset, root_first, root_count, "", 0, &mut paths)?;
+    Ok(paths)
+}
+
+fn list_folder(
+    bytes: &[u8],
+    table_offset: usize,
+    first: usize,
+    count: usize,
+    prefix: &str,
+    depth: usize,
+    paths: &mut Vec<String>
+) -> Result<(), String> {
+    if depth > MAX_DIRECTORY_DEPTH {
+        return Err("folders are nested too deep".to_string());
+    }
+    for index in first..first.saturating_add(count) {
+        let offset = index
+            .checked_mul(RES_ENTRY_SIZE)
+            .and_then(|it| it.checked_add(table_offset))
+            .ok_or_else(|| "entry index is out of range".to_string())?;
+        let entry = bytes
+            .get(offset..offset + RES_ENTRY_SIZE)
+            .ok_or_else(|| format!("entry {} is out of the archive", index))?;
+        let field = |at: usize| read_u32(entry, at).unwrap() as usize;
+        let name_bytes = &entry[16..];
+        let name_len = name_bytes.iter().position(|&it| it == 0).unwrap_or(name_bytes.len());
+        let path = format!("{}{}", prefix, String::from_utf8_lossy(&name_bytes[..name_len]));
+        if field(12) as u32 == RES_DIRECTORY {
+            list_folder(bytes, table_offset, field(4), field(8), &format!("{}/", path), depth + 1, paths)?;
+        } else {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|it| u32::from_le_bytes(it.try_into().unwrap()))
+}
+
+/// Returns width, height, frame count and duration in ms of a smacker stream
+pub fn read_smacker_header(bytes: &[u8]) -> Option<(u32, u32, u32, f32)> {
+    let signature = bytes.get(0..4)?;
+    if signature != b"SMK2" && signature != b"SMK4" {
+        return None;
+    }
+    let width = read_u32(bytes, 4)?;
+    let height = read_u32(bytes, 8)?;
+    let frames = read_u32(bytes, 12)?;
+    let frame_rate = read_u32(bytes, 16)? as i32;
+    let frame_duration_ms = if frame_rate > 0 {
+        frame_rate as f32
+    } else if frame_rate < 0 {
+        // negative rates are given in units of 10 microseconds
+        -frame_rate as f32 / 100.0
+    } else {
+        100.0
+    };
+    Some((width, height, frames, frames as f32 * frame_duration_ms))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Smacker header which is enough for `read_smacker_header`: 320x200, 10 frames, 100 ms each
+    pub fn smk_header() -> Vec<u8> {
+        let mut bytes = b"SMK2".to_vec();
+        for value in &[320u32, 200, 10, 100] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// RES archive with the given files, a `FOLDER/name` path puts the file into a folder
+    pub fn make_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut root = Vec::new();
+        let mut folders: Vec<(&str, Vec<(&str, &[u8])>)> = Vec::new();
+        for &(path, data) in files {
+            match path.find('/') {
+                Some(split) => {
+                    let (folder, name) = (&path[..split], &path[split + 1..]);
+                    match folders.iter_mut().find(|(it, _)| *it == folder) {
+                        Some((_, children)) => children.push((name, data)),
+                        None => folders.push((folder, vec![(name, data)]))
+                    }
+                }
+                None => root.push((path, data))
+            }
+        }
+
+        let data_size: usize = files.iter().map(|(_, data)| data.len()).sum();
+        let table_offset = RES_HEADER_SIZE + data_size;
+        let root_count = (root.len() + folders.len()) as u32;
+        let entry_count = (files.len() + folders.len()) as u32;
+        let mut bytes = Vec::new();
+        for value in &[RES_SIGNATURE, 0, root_count, 0, table_offset as u32, entry_count * 32] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // root files, then root folders, then the children of every folder in order
+        let mut table = Vec::new();
+        let mut push_entry = |name: &str, offset: u32, size: u32, kind: u32| {
+            for value in &[0, offset, size, kind] {
+                table.extend_from_slice(&value.to_le_bytes());
+            }
+            let mut name_bytes = [0u8; 16];
+            name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+            table.extend_from_slice(&name_bytes);
+        };
+        let children = folders.iter().flat_map(|(_, children)| children.iter());
+        let mut data_offsets = Vec::new();
+        for (_, data) in root.iter().chain(children.clone()) {
+            data_offsets.push(bytes.len() as u32);
+            bytes.extend_from_slice(data);
+        }
+        let mut data_offsets = data_offsets.into_iter();
+        for (name, data) in &root {
+            push_entry(name, data_offsets.next().unwrap(), data.len() as u32, 0);
+        }
+        let mut first_child = root_count;
+        for (folder, children) in &folders {
+            push_entry(folder, first_child, children.len() as u32, RES_DIRECTORY);
+            first_child += children.len() as u32;
+        }
+        for (name, data) in children {
+            push_entry(name, data_offsets.next().unwrap(), data.len() as u32, 0);
+        }
+        bytes.extend_from_slice(&table);
+        bytes
+    }
+
+    #[test]
+    fn entries_are_listed_with_their_folders() {
+        let smk = smk_header();
+        let archive = make_archive(&[
+            ("readme.txt", b"hello"),
+            ("INTRO/01.smk", &smk),
+            ("OUTRO/final.SMK", &smk)
+        ]);
+        assert_eq!(
+            list_entries(&archive).unwrap(),
+            vec!["readme.txt", "INTRO/01.smk", "OUTRO/final.SMK"]
+        );
+        assert!(list_entries(b"not an archive").is_err());
+        assert!(list_entries(&archive[..archive.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn browser_lists_every_smacker_entry_of_video_archives() {
+        let assets_dir = std::env::temp_dir().join(format!("play_smacker_browser_{}", std::process::id()));
+        fs::create_dir_all(&assets_dir).unwrap();
+        let smk = smk_header();
+        let video = make_archive(&[
+            ("INTRO/01.smk", &smk),
+            ("CUSTOM/any name.smk", &smk),
+            ("INTRO/broken.smk", b"SMK"),
+            ("INTRO/notes.txt", b"not a video")
+        ]);
+        fs::write(assets_dir.join("VIDEO9.RES"), &video).unwrap();
+        fs::write(assets_dir.join("GRAPHICS.RES"), make_archive(&[("skipped.smk", &smk)])).unwrap();
+
+        let browser = VideoBrowser::scan(&assets_dir);
+        fs::remove_dir_all(&assets_dir).unwrap();
+
+        let entries: Vec<&str> = browser.videos.iter().map(|it| it.entry.as_str()).collect();
+        assert_eq!(entries, vec!["INTRO/01.smk", "CUSTOM/any name.smk"]);
+        let video = &browser.videos[0];
+        assert_eq!(video.archive, "VIDEO9.RES");
+        assert_eq!((video.width, video.height, video.frames), (320, 200, 10));
+        assert_eq!(video.duration_ms, 1000.0);
+        assert_eq!(browser.errors.len(), 1, "{:?}", browser.errors);
+    }
+}
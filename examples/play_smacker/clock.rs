@@ -0,0 +1,158 @@
+use std::time::Instant;
+
+pub const MAX_REAL_TIME_STEP_MS: f32 = 100.0;
+
+pub trait FrameClock {
+    /// Returns the amount of milliseconds to advance the playback by since the previous call
+    fn advance(&mut self) -> f32;
+}
+
+/// Wall clock driven time source. Steps are clamped, so a window hitch
+/// does not make the player jump over a bunch of frames at once
+pub struct RealTimeClock {
+    last_instant: Instant,
+    max_step_ms: f32
+}
+
+impl RealTimeClock {
+    pub fn new() -> Self {
+        Self {
+            last_instant: Instant::now(),
+            max_step_ms: MAX_REAL_TIME_STEP_MS
+        }
+    }
+}
+
+impl Default for RealTimeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameClock for RealTimeClock {
+    fn advance(&mut self) -> f32 {
+        let dt = self.last_instant.elapsed().as_micros() as f32 / 1000.0;
+        self.last_instant = Instant::now();
+        dt.min(self.max_step_ms)
+    }
+}
+
+/// Deterministic time source which advances by exactly the same step each call
+pub struct FixedStepClock {
+    step_ms: f32
+}
+
+impl FixedStepClock {
+    pub fn new(step_ms: f32) -> Self {
+        Self { step_ms }
+    }
+}
+
+impl FrameClock for FixedStepClock {
+    fn advance(&mut self) -> f32 {
+        self.step_ms
+    }
+}
+
+/// Picks the clock out of the command line: `--fixed-step <ms>` selects `FixedStepClock`,
+/// otherwise `RealTimeClock` is used
+pub fn clock_from_args<I: Iterator<Item = String>>(args: I) -> Result<Box<dyn FrameClock>, String> {
+    let mut args = args.skip_while(|arg| arg != "--fixed-step");
+    if args.next().is_none() {
+        return Ok(Box::new(RealTimeClock::new()));
+    }
+    let usage = |value: &str| format!("--fixed-step expects a positive number of milliseconds, got {}", value);
+    let step = args.next().ok_or_else(|| usage("nothing"))?;
+    let step_ms = step
+        .parse::<f32>()
+        .ok()
+        .filter(|it| it.is_finite() && *it > 0.0)
+        .ok_or_else(|| usage(&step))?;
+    Ok(Box::new(FixedStepClock::new(step_ms)))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::browser::read_smacker_header,
+        rom_res_rs::ResourceFile,
+        rom_media_rs::video::{SmackerPlayer, PlayerState, RenderingFramesState},
+        std::io::Cursor
+    };
+
+    const FADE_MS: f32 = 800.0;
+    const STEP_MS: f32 = 10.0;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(|it| it.to_string())
+    }
+
+    /// The intro clip out of the game archives, which are not shipped with the repository.
+    /// Tests using it are ignored by default, run them with `cargo test -- --ignored`
+    /// once `VIDEO4.RES` is put into `examples/assets`
+    fn intro_clip() -> Vec<u8> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/assets/VIDEO4.RES");
+        let archive = std::fs::read(path).expect("VIDEO4.RES is missing in examples/assets");
+        let mut cursor = Cursor::new(&archive[..]);
+        let mut resource_file = ResourceFile::new(&mut cursor).expect("failed to open VIDEO4.RES");
+        resource_file.get_resource_bytes("INTRO/04.smk").expect("VIDEO4.RES has no INTRO/04.smk")
+    }
+
+    #[test]
+    fn fixed_step_clock_advances_by_exact_step() {
+        let mut clock = FixedStepClock::new(STEP_MS);
+        let elapsed: f32 = (0..100).map(|_| clock.advance()).sum();
+        assert_eq!(elapsed, 1000.0);
+    }
+
+    #[test]
+    fn fixed_step_is_parsed_from_args() {
+        let mut clock = clock_from_args(args("play_smacker --fixed-step 25")).unwrap();
+        assert_eq!(clock.advance(), 25.0);
+        assert!(clock_from_args(args("play_smacker --browse")).is_ok());
+    }
+
+    #[test]
+    fn malformed_fixed_step_is_an_error() {
+        assert!(clock_from_args(args("play_smacker --fixed-step fast")).is_err());
+        assert!(clock_from_args(args("play_smacker --fixed-step -5")).is_err());
+        assert!(clock_from_args(args("play_smacker --fixed-step NaN")).is_err());
+        assert!(clock_from_args(args("play_smacker --fixed-step --browse")).is_err());
+        assert!(clock_from_args(args("play_smacker --fixed-step")).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs VIDEO4.RES of the game in examples/assets"]
+    fn fade_in_lasts_its_duration() {
+        let smk = intro_clip();
+        let mut player = SmackerPlayer::load_from_stream(&mut Cursor::new(smk)).unwrap();
+        player.set_fade_in_ms(FADE_MS as _);
+        let mut clock = FixedStepClock::new(STEP_MS);
+        let mut fading_steps = 0;
+        while let Ok(PlayerState::FadeIn(_)) = player.frame(clock.advance()) {
+            fading_steps += 1;
+        }
+        let expected_steps = (FADE_MS / STEP_MS) as i32;
+        assert!((fading_steps - expected_steps).abs() <= 1, "faded in for {} steps", fading_steps);
+    }
+
+    #[test]
+    #[ignore = "needs VIDEO4.RES of the game in examples/assets"]
+    fn frames_follow_the_clip_frame_rate() {
+        let smk = intro_clip();
+        let (_, _, frames, duration_ms) = read_smacker_header(&smk).unwrap();
+        let frame_ms = duration_ms / frames as f32;
+        let mut player = SmackerPlayer::load_from_stream(&mut Cursor::new(smk)).unwrap();
+        // Every step lasts half of a frame, so a new frame is expected on every second step
+        let mut clock = FixedStepClock::new(frame_ms / 2.0);
+        let steps = 40;
+        let rendered = (0..steps)
+            .filter(|_| matches!(
+                player.frame(clock.advance()),
+                Ok(PlayerState::IsRendering { state: RenderingFramesState::RenderedNewFrame, .. })
+            ))
+            .count() as i32;
+        assert!((rendered - steps / 2).abs() <= 1, "rendered {} frames in {} steps", rendered, steps);
+    }
+}
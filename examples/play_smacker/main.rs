@@ -0,0 +1,458 @@
+mod clock;
+mod sequencer;
+mod subtitles;
+mod browser;
+
+use orom_miniquad::*;
+use rom_media_rs::image_rendering::bmp_sprite_decorators::TrueColorSurfaceSprite;
+use rom_media_rs::image_rendering::blittable::{Blittable, BlitBuilder};
+use rom_media_rs::video::SmackerPlayer;
+use std::path::{Path, PathBuf};
+use egui_integration::EguiMq;
+use egui::{Align2, Color32, FontDefinitions, FontFamily, TextStyle};
+use crate::clock::{FrameClock, clock_from_args};
+use crate::sequencer::{Clip, CutsceneSequencer, SequencerEvent};
+use crate::subtitles::Subtitles;
+use crate::browser::VideoBrowser;
+
+const ASSETS_ENV_VAR: &str = "PLAY_SMACKER_ASSETS";
+const DEFAULT_ARCHIVE: &str = "VIDEO4.RES";
+const VIDEO_PATH: &str = "INTRO/04.smk";
+const ERROR_DISPLAY_MS: f32 = 4000.0;
+/// Played when the intro archive can not be found next to the executable
+const EMBEDDED_ARCHIVE: &[u8] = include_bytes!("../assets/VIDEO4.RES");
+const JETBRAINS_MONO_FONT: &[u8] = include_bytes!("../assets/JetBrainsMono-Medium.ttf");
+
+#[repr(C)]
+struct Vec2 {
+    x: f32,
+    y: f32,
+}
+#[repr(C)]
+struct Vertex {
+    pos: Vec2,
+    uv: Vec2,
+}
+
+struct Stage {
+    pipeline: Pipeline,
+    bindings: Bindings,
+    stage_surface: TrueColorSurfaceSprite,
+    sequencer: CutsceneSequencer,
+    clock: Box<dyn FrameClock>,
+    egui: EguiMq,
+    subtitles: Option<Subtitles>,
+    browser: Option<VideoBrowser>,
+    show_browser: bool,
+    picked_video: Option<usize>,
+    playback_error: Option<(String, f32)>
+}
+
+fn setup_player(player: &mut SmackerPlayer) {
+    player.set_fade_in_ms(800);
+    player.set_fade_out_ms(800);
+}
+
+impl Stage {
+    pub fn new(
+        ctx: &mut Context,
+        clock: Box<dyn FrameClock>,
+        assets_dir: &Path,
+        clips: Vec<Clip>,
+        browser: Option<VideoBrowser>
+    ) -> Stage {
+        // In the browser mode nothing is played until a video is picked from the list
+        let sequencer = match &browser {
+            Some(browser) => CutsceneSequencer::new(&browser.assets_dir, Vec::new()),
+            None => CutsceneSequencer::new(assets_dir, clips)
+        }
+            .with_fallback_archive(DEFAULT_ARCHIVE, EMBEDDED_ARCHIVE)
+            .with_player_setup(setup_player);
+
+        #[rustfmt::skip]
+            let vertices: [Vertex; 4] = [
+            Vertex { pos : Vec2 { x: -1., y: -1. }, uv: Vec2 { x: 0., y: 1. } },
+            Vertex { pos : Vec2 { x:  1., y: -1. }, uv: Vec2 { x: 1., y: 1. } },
+            Vertex { pos : Vec2 { x:  1., y:  1. }, uv: Vec2 { x: 1., y: 0. } },
+            Vertex { pos : Vec2 { x: -1., y:  1. }, uv: Vec2 { x: 0., y: 0. } },
+        ];
+        let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices);
+
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+        let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &indices);
+
+        let stage_surface = TrueColorSurfaceSprite::new(320, 240);
+        let casted = bytemuck::cast_slice(stage_surface.color_data());
+        let texture = Texture::from_data_and_format(
+            ctx,
+            &casted,
+            TextureParams {
+                format: TextureFormat::RGBA8,
+                wrap: TextureWrap::Clamp,
+                filter: FilterMode::Nearest,
+                width: stage_surface.get_width() as u32,
+                height: stage_surface.get_height() as u32
+            }
+        );
+
+        let bindings = Bindings {
+            vertex_buffers: vec![vertex_buffer],
+            index_buffer,
+            images: vec![texture],
+        };
+
+        let shader = Shader::new(ctx, shader::VERTEX, shader::FRAGMENT, shader::meta()).unwrap();
+
+        let pipeline = Pipeline::new(
+            ctx,
+            &[BufferLayout::default()],
+            &[
+                VertexAttribute::new("pos", VertexFormat::Float2),
+                VertexAttribute::new("uv", VertexFormat::Float2),
+            ],
+            shader,
+        );
+
+        let egui = EguiMq::new(ctx);
+        let mut fonts = FontDefinitions::default();
+        fonts.font_data
+            .insert("JetBrains Mono".to_owned(), std::borrow::Cow::Borrowed(JETBRAINS_MONO_FONT));
+        fonts.fonts_for_family
+            .get_mut(&FontFamily::Proportional)
+            .unwrap()
+            .insert(0, "JetBrains Mono".to_owned());
+        fonts.family_and_size
+            .insert(TextStyle::Heading, (FontFamily::Proportional, 20.0));
+        egui.egui_ctx().set_fonts(fonts);
+
+        Stage {
+            pipeline,
+            bindings,
+            stage_surface,
+            sequencer,
+            clock,
+            egui,
+            subtitles: None,
+            show_browser: browser.is_some(),
+            browser,
+            picked_video: None,
+            playback_error: None
+        }
+    }
+}
+
+impl EventHandler for Stage {
+    fn update(&mut self, _ctx: &mut Context) {
+        if let Some(video_id) = self.picked_video.take() {
+            self.play_from_browser(video_id);
+        }
+        let dt = self.clock.advance();
+        if let Some((_, remaining_ms)) = &mut self.playback_error {
+            *remaining_ms -= dt;
+            if *remaining_ms <= 0.0 {
+                self.playback_error = None;
+            }
+        }
+        match self.sequencer.frame(dt) {
+            SequencerEvent::Finished => {
+                if self.browser.is_some() {
+                    self.show_browser = true;
+                } else if self.playback_error.is_none() {
+                    _ctx.quit()
+                }
+            },
+            SequencerEvent::ClipFailed { error, .. } => {
+                // The last good frame stays on screen while the error is shown
+                self.playback_error = Some((error, ERROR_DISPLAY_MS));
+            },
+            SequencerEvent::ClipStarted(_) => {
+                self.subtitles = self.sequencer
+                    .current_clip()
+                    .and_then(|clip| clip.subtitles.as_ref())
+                    .and_then(|path| {
+                        Subtitles::load(path)
+                            .map_err(|e| eprintln!("{}", e))
+                            .ok()
+                    });
+                // Clips are not obliged to share dimensions, so wipe leftovers of the previous one
+                self.stage_surface = TrueColorSurfaceSprite::new(320, 240);
+                let casted = bytemuck::cast_slice(self.stage_surface.color_data());
+                self.bindings.images[0].update(_ctx, casted);
+            },
+            SequencerEvent::NewFrame => {
+                if let Some(player) = self.sequencer.player() {
+                    BlitBuilder::try_create(&mut self.stage_surface, player)
+                        .expect("failed to create blit builder")
+                        .with_dest_pos(0, 30)
+                        .blit();
+                }
+
+                let casted = bytemuck::cast_slice(self.stage_surface.color_data());
+                self.bindings.images[0].update(_ctx, casted);
+            },
+            SequencerEvent::Idle => ()
+        }
+    }
+
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32) {
+        self.egui.mouse_motion_event(ctx, x, y);
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, dx: f32, dy: f32) {
+        self.egui.mouse_wheel_event(ctx, dx, dy);
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        self.egui.mouse_button_down_event(ctx, button, x, y);
+    }
+
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        self.egui.mouse_button_up_event(ctx, button, x, y);
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods, repeat: bool) {
+        self.egui.key_down_event(ctx, keycode, keymods);
+        if repeat { return; }
+        match keycode {
+            KeyCode::Escape if self.show_browser || self.sequencer.is_finished() => ctx.quit(),
+            KeyCode::Space | KeyCode::Enter => self.sequencer.skip_clip(),
+            KeyCode::Escape => self.sequencer.skip_sequence(),
+            _ => {}
+        }
+    }
+
+    fn key_up_event(&mut self, _ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
+        self.egui.key_up_event(keycode, keymods);
+    }
+
+    fn draw(&mut self, ctx: &mut Context) {
+        self.egui.begin_frame(ctx);
+        self.subtitles_ui();
+        self.browser_ui();
+        self.error_ui();
+        self.egui.end_frame(ctx);
+
+        ctx.begin_default_pass(Default::default());
+
+        ctx.apply_pipeline(&self.pipeline);
+        ctx.apply_bindings(&self.bindings);
+
+        ctx.apply_uniforms(&shader::Uniforms {
+            offset: (0.0, 0.0),
+        });
+
+        ctx.draw(0, 6, 1);
+
+        ctx.end_render_pass();
+
+        self.egui.draw(ctx);
+
+        ctx.commit_frame();
+    }
+}
+
+impl Stage {
+    fn play_from_browser(&mut self, video_id: usize) {
+        let browser = match &self.browser {
+            Some(browser) => browser,
+            None => return
+        };
+        let video = &browser.videos[video_id];
+        self.sequencer = CutsceneSequencer::new(
+            &browser.assets_dir,
+            vec![Clip::new(&video.archive, &video.entry)]
+        ).with_player_setup(setup_player);
+        self.show_browser = false;
+    }
+
+    fn browser_ui(&mut self) {
+        if !self.show_browser { return; }
+        let browser = match &self.browser {
+            Some(browser) => browser,
+            None => return
+        };
+        let picked_video = &mut self.picked_video;
+
+        egui::Window::new("videos")
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .default_height(400.0)
+            .show(self.egui.egui_ctx(), |ui| {
+                for error in &browser.errors {
+                    ui.colored_label(Color32::from_rgb(0xEE, 0x55, 0x55), error);
+                }
+                if browser.videos.is_empty() {
+                    ui.label(format!("No videos found in {}", browser.assets_dir.display()));
+                }
+                egui::ScrollArea::auto_sized().show(ui, |ui| {
+                    for (id, video) in browser.videos.iter().enumerate() {
+                        let text = format!(
+                            "{}:{:<16} {:>3}x{:<3} {:>5} frames {:>6.1}s",
+                            video.archive,
+                            video.entry,
+                            video.width,
+                            video.height,
+                            video.frames,
+                            video.duration_ms / 1000.0
+                        );
+                        let label = egui::SelectableLabel::new(false, text);
+                        if ui.add(label).clicked() {
+                            *picked_video = Some(id);
+                        }
+                    }
+                });
+            });
+    }
+
+    fn error_ui(&mut self) {
+        if let Some((error, _)) = &self.playback_error {
+            egui::Area::new("playback error")
+                .anchor(Align2::CENTER_TOP, [0.0, 8.0])
+                .show(self.egui.egui_ctx(), |ui| {
+                    ui.colored_label(Color32::from_rgb(0xEE, 0x55, 0x55), error);
+                });
+        }
+    }
+
+    fn subtitles_ui(&mut self) {
+        let text = match &self.subtitles {
+            Some(subtitles) => subtitles.cue_at(self.sequencer.playback_ms()),
+            None => None
+        };
+        let text = match text {
+            Some(text) => text,
+            None => return
+        };
+
+        // Video frame is placed at (0, 30) of the 320x240 surface, so the letterbox
+        // below it takes the bottom part of the window
+        egui::Area::new("subtitles")
+            .anchor(Align2::CENTER_BOTTOM, [0.0, -8.0])
+            .show(self.egui.egui_ctx(), |ui| {
+                ui.vertical_centered(|ui| {
+                    for line in text.lines() {
+                        ui.add(egui::Label::new(line)
+                            .text_style(TextStyle::Heading)
+                            .text_color(Color32::from_rgb(0xEE, 0xEE, 0xD0)));
+                    }
+                });
+            });
+    }
+}
+
+mod shader {
+    use orom_miniquad ::*;
+
+    pub const VERTEX: &str = r#"#version 100
+    attribute vec2 pos;
+    attribute vec2 uv;
+
+    uniform vec2 offset;
+
+    varying lowp vec2 texcoord;
+
+    void main() {
+        gl_Position = vec4(pos + offset, 0, 1);
+        texcoord = uv;
+    }"#;
+
+    pub const FRAGMENT: &str = r#"#version 100
+    varying lowp vec2 texcoord;
+
+    uniform sampler2D tex;
+
+    void main() {
+        gl_FragColor = texture2D(tex, texcoord);
+    }"#;
+
+    pub fn meta() -> ShaderMeta {
+        ShaderMeta {
+            images: vec!["tex".to_string()],
+            uniforms: UniformBlockLayout {
+                uniforms: vec![UniformDesc::new("offset", UniformType::Float2)],
+            },
+        }
+    }
+
+    #[repr(C)]
+    pub struct Uniforms {
+        pub offset: (f32, f32),
+    }
+}
+
+fn clips_from_args() -> Vec<Clip> {
+    let mut clips = Vec::new();
+    let mut default_subtitles = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--clip" => match args.next().as_deref().and_then(Clip::parse) {
+                Some(clip) => clips.push(clip),
+                None => eprintln!("--clip expects an ARCHIVE.RES:PATH/TO/ENTRY.smk argument")
+            },
+            // subtitles are attached to the clip given right before them
+            "--subtitles" => match (args.next(), clips.last_mut()) {
+                (Some(path), Some(clip)) => clip.subtitles = Some(PathBuf::from(path)),
+                (Some(path), None) => default_subtitles = Some(PathBuf::from(path)),
+                (None, _) => eprintln!("--subtitles expects a path to an .srt file")
+            },
+            _ => {}
+        }
+    }
+    if clips.is_empty() {
+        let mut clip = Clip::new(DEFAULT_ARCHIVE, VIDEO_PATH);
+        clip.subtitles = default_subtitles;
+        clips.push(clip);
+    }
+    clips
+}
+
+/// Game archives are looked up in `--assets <dir>`, then in the directory given by
+/// the `PLAY_SMACKER_ASSETS` variable, then in the `assets` directory next to the executable
+fn assets_dir_from_args() -> PathBuf {
+    let from_args = std::env::args()
+        .skip_while(|arg| arg != "--assets")
+        .nth(1)
+        .map(PathBuf::from);
+    from_args
+        .or_else(|| std::env::var_os(ASSETS_ENV_VAR).map(PathBuf::from))
+        .or_else(|| {
+            std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(|dir| dir.join("assets")))
+        })
+        .unwrap_or_else(|| PathBuf::from("assets"))
+}
+
+fn browser_from_args(assets_dir: &Path) -> Option<VideoBrowser> {
+    if !std::env::args().any(|arg| arg == "--browse") {
+        return None;
+    }
+    let assets_dir = std::env::args()
+        .skip_while(|arg| arg != "--browse")
+        .nth(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| assets_dir.to_path_buf());
+    Some(VideoBrowser::scan(&assets_dir))
+}
+
+fn main() {
+    let clock = clock_from_args(std::env::args()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    let assets_dir = assets_dir_from_args();
+    let clips = clips_from_args();
+    let browser = browser_from_args(&assets_dir);
+    orom_miniquad::start(conf::Conf {
+        window_width: 640,
+        window_height: 480,
+        window_title: "play_smacker_miniquad".to_string(),
+        ..Default::default()
+    }, move |mut ctx| {
+        UserData::owning(
+            Stage::new(&mut ctx, clock, &assets_dir, clips, browser),
+            ctx
+        )
+    });
+}
\ No newline at end of file
@@ -0,0 +1,259 @@
+use {
+    rom_res_rs::ResourceFile,
+    rom_media_rs::video::{SmackerPlayer, PlayerState, RenderingFramesState},
+    std::{
+        collections::HashMap,
+        io::{Cursor, Read},
+        fs::File,
+        path::{Path, PathBuf}
+    }
+};
+
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub archive: String,
+    pub entry: String,
+    pub subtitles: Option<PathBuf>
+}
+
+impl Clip {
+    pub fn new(archive: &str, entry: &str) -> Self {
+        Self { archive: archive.to_string(), entry: entry.to_string(), subtitles: None }
+    }
+
+    /// Parses clips given in the `ARCHIVE.RES:PATH/TO/ENTRY.smk` form
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(archive), Some(entry)) if !archive.is_empty() && !entry.is_empty() => {
+                Some(Self::new(archive, entry))
+            }
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum SequencerEvent {
+    Idle,
+    NewFrame,
+    ClipStarted(usize),
+    ClipFailed { clip_id: usize, error: String },
+    Finished
+}
+
+pub struct CutsceneSequencer {
+    assets_dir: PathBuf,
+    clips: Vec<Clip>,
+    archives: HashMap<String, Vec<u8>>,
+    fallback_archives: HashMap<String, &'static [u8]>,
+    current_clip: usize,
+    player: Option<SmackerPlayer>,
+    player_setup: Box<dyn Fn(&mut SmackerPlayer)>,
+    clip_pending: bool,
+    playback_ms: f32,
+    rendered_frames: usize
+}
+
+impl CutsceneSequencer {
+    pub fn new(assets_dir: &Path, clips: Vec<Clip>) -> Self {
+        Self {
+            assets_dir: assets_dir.to_path_buf(),
+            clips,
+            archives: HashMap::new(),
+            fallback_archives: HashMap::new(),
+            current_clip: 0,
+            player: None,
+            player_setup: Box::new(|_| {}),
+            clip_pending: true,
+            playback_ms: 0.0,
+            rendered_frames: 0
+        }
+    }
+
+    /// Allows to tune every player before its clip starts (fades and so on)
+    pub fn with_player_setup<F: Fn(&mut SmackerPlayer) + 'static>(mut self, setup: F) -> Self {
+        self.player_setup = Box::new(setup);
+        self
+    }
+
+    /// Archive contents to use when the archive is missing from the assets directory
+    pub fn with_fallback_archive(mut self, archive: &str, bytes: &'static [u8]) -> Self {
+        self.fallback_archives.insert(archive.to_string(), bytes);
+        self
+    }
+
+    pub fn current_clip(&self) -> Option<&Clip> {
+        self.clips.get(self.current_clip)
+    }
+
+    /// Position on the timeline of the current clip, counted from its first frame,
+    /// so the fade in does not shift it
+    pub fn playback_ms(&self) -> f32 {
+        self.playback_ms
+    }
+
+    pub fn player(&self) -> Option<&SmackerPlayer> {
+        self.player.as_ref()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current_clip >= self.clips.len()
+    }
+
+    pub fn skip_clip(&mut self) {
+        if self.is_finished() { return; }
+        self.player = None;
+        self.current_clip += 1;
+        self.clip_pending = true;
+    }
+
+    pub fn skip_sequence(&mut self) {
+        self.player = None;
+        self.current_clip = self.clips.len();
+        self.clip_pending = false;
+    }
+
+    pub fn frame(&mut self, dt: f32) -> SequencerEvent {
+        if self.is_finished() {
+            return SequencerEvent::Finished;
+        }
+        if self.clip_pending {
+            self.clip_pending = false;
+            return match self.load_player(self.current_clip) {
+                Ok(player) => {
+                    self.player = Some(player);
+                    self.playback_ms = 0.0;
+                    self.rendered_frames = 0;
+                    SequencerEvent::ClipStarted(self.current_clip)
+                }
+                Err(error) => self.fail_clip(error)
+            };
+        }
+        let player = match &mut self.player {
+            Some(player) => player,
+            None => return SequencerEvent::Idle
+        };
+        let state = match player.frame(dt) {
+            Ok(state) => state,
+            Err(e) => {
+                let error = format!("failed to decode frame {}: {:?}", self.rendered_frames, e);
+                return self.fail_clip(error);
+            }
+        };
+        if !matches!(state, PlayerState::FadeIn(_)) {
+            self.playback_ms += dt;
+        }
+        match state {
+            PlayerState::FinishedPlaying => {
+                self.skip_clip();
+                if self.is_finished() {
+                    SequencerEvent::Finished
+                } else {
+                    SequencerEvent::Idle
+                }
+            },
+            PlayerState::IsRendering {
+                state: RenderingFramesState::RenderedNewFrame,
+                ..
+            } => {
+                self.rendered_frames += 1;
+                SequencerEvent::NewFrame
+            },
+            PlayerState::FadeIn(_)
+            |
+            PlayerState::FadeOut(_) => SequencerEvent::NewFrame,
+            _ => SequencerEvent::Idle
+        }
+    }
+
+    /// Drops the broken clip and moves on to the next one
+    fn fail_clip(&mut self, error: String) -> SequencerEvent {
+        let clip_id = self.current_clip;
+        let clip = &self.clips[clip_id];
+        let error = format!("{}:{}: {}", clip.archive, clip.entry, error);
+        eprintln!("{}", error);
+        self.skip_clip();
+        SequencerEvent::ClipFailed { clip_id, error }
+    }
+
+    fn load_player(&mut self, clip_id: usize) -> Result<SmackerPlayer, String> {
+        let clip = self.clips[clip_id].clone();
+        self.load_archive(&clip.archive)?;
+        let mut cursor = Cursor::new(&self.archives[&clip.archive][..]);
+        let mut resource_file = ResourceFile::new(&mut cursor)
+            .map_err(|e| format!("failed to open {}: {:?}", clip.archive, e))?;
+
+        let smk_file = resource_file.get_resource_bytes(&clip.entry)
+            .map_err(|e| format!("failed to load resource {}: {:?}", clip.entry, e))?;
+
+        let mut cursor = Cursor::new(smk_file);
+        let mut player = SmackerPlayer::load_from_stream(&mut cursor)
+            .map_err(|e| format!("failed to load smacker file {}: {:?}", clip.entry, e))?;
+
+        (self.player_setup)(&mut player);
+        Ok(player)
+    }
+
+    fn load_archive(&mut self, archive: &str) -> Result<(), String> {
+        if !self.archives.contains_key(archive) {
+            let path = self.assets_dir.join(archive);
+            let mut bytes = Vec::new();
+            let read_result = File::open(&path)
+                .and_then(|mut file| file.read_to_end(&mut bytes));
+            if let Err(e) = read_result {
+                bytes = self.fallback_archives
+                    .get(archive)
+                    .map(|it| it.to_vec())
+                    .ok_or_else(|| format!("failed to read {}: {}", path.display(), e))?;
+            }
+            self.archives.insert(archive.to_string(), bytes);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::browser::tests::make_archive};
+
+    /// Beginning of a smacker header which ends right after the frame count
+    fn truncated_smk() -> Vec<u8> {
+        let mut bytes = b"SMK2".to_vec();
+        for value in &[320u32, 240, 10] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn truncated_smacker_file_is_an_error() {
+        let smk = truncated_smk();
+        for len in 0..=smk.len() {
+            let result = SmackerPlayer::load_from_stream(&mut Cursor::new(smk[..len].to_vec()));
+            assert!(result.is_err(), "{} bytes of a header were accepted", len);
+        }
+    }
+
+    #[test]
+    fn broken_clip_is_skipped() {
+        let archive = make_archive(&[("broken.smk", &truncated_smk())]);
+        let archive: &'static [u8] = Box::leak(archive.into_boxed_slice());
+        let clips = vec![
+            Clip::new("TEST.RES", "broken.smk"),
+            Clip::new("TEST.RES", "missing.smk")
+        ];
+        let mut sequencer = CutsceneSequencer::new(Path::new("no such directory"), clips)
+            .with_fallback_archive("TEST.RES", archive);
+
+        match sequencer.frame(10.0) {
+            SequencerEvent::ClipFailed { clip_id: 0, error } => {
+                assert!(error.contains("failed to load smacker file"), "{}", error)
+            },
+            event => panic!("unexpected {:?}", event)
+        }
+        assert_eq!(sequencer.current_clip().map(|it| it.entry.as_str()), Some("missing.smk"));
+        assert!(matches!(sequencer.frame(10.0), SequencerEvent::ClipFailed { clip_id: 1, .. }));
+        assert_eq!(sequencer.frame(10.0), SequencerEvent::Finished);
+    }
+}
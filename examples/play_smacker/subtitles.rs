@@ -0,0 +1,128 @@
+use std::{fs, path::Path};
+
+pub struct SubtitleCue {
+    pub start_ms: f32,
+    pub end_ms: f32,
+    pub text: String
+}
+
+pub struct Subtitles {
+    cues: Vec<SubtitleCue>
+}
+
+impl Subtitles {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&decode_text(&bytes))
+    }
+
+    /// Parses SRT content: numbered blocks separated by blank lines, each holding
+    /// a `HH:MM:SS,mmm --> HH:MM:SS,mmm` line followed by the lines of text
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut cues = Vec::new();
+        let mut lines = content.lines().map(|l| l.trim_end()).peekable();
+        while lines.peek().is_some() {
+            while let Some(&"") = lines.peek() { lines.next(); }
+            let mut header = match lines.next() {
+                Some(line) => line,
+                None => break
+            };
+            if !header.contains("-->") {
+                // skip the cue counter
+                header = lines.next().unwrap_or("");
+            }
+            let mut parts = header.split("-->");
+            let (start_ms, end_ms) = match (parts.next(), parts.next()) {
+                (Some(start), Some(end)) => (parse_timestamp(start)?, parse_timestamp(end)?),
+                _ => return Err(format!("malformed subtitle timing line: {}", header))
+            };
+            let mut text_lines = Vec::new();
+            while let Some(line) = lines.peek() {
+                if line.is_empty() { break; }
+                text_lines.push(*line);
+                lines.next();
+            }
+            cues.push(SubtitleCue { start_ms, end_ms, text: text_lines.join("\n") });
+        }
+        cues.sort_by(|l, r| l.start_ms.total_cmp(&r.start_ms));
+        Ok(Self { cues })
+    }
+
+    pub fn cue_at(&self, time_ms: f32) -> Option<&str> {
+        self.cues
+            .iter()
+            .find(|cue| cue.start_ms <= time_ms && time_ms < cue.end_ms)
+            .map(|cue| cue.text.as_str())
+    }
+}
+
+fn parse_timestamp(s: &str) -> Result<f32, String> {
+    let malformed = || format!("malformed subtitle timestamp: {}", s.trim());
+    let s = s.trim().replace(',', ".");
+    let mut parts = s.split(':');
+    let (hours, minutes, seconds) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(h), Some(m), Some(s)) => (h, m, s),
+        _ => return Err(malformed())
+    };
+    let hours: f32 = hours.parse().map_err(|_| malformed())?;
+    let minutes: f32 = minutes.parse().map_err(|_| malformed())?;
+    let seconds: f32 = seconds.parse().map_err(|_| malformed())?;
+    let time_ms = ((hours * 60.0 + minutes) * 60.0 + seconds) * 1000.0;
+    if !time_ms.is_finite() {
+        return Err(malformed());
+    }
+    Ok(time_ms)
+}
+
+/// Decodes UTF-8 text (with or without BOM), falling back to CP1251
+/// which is what most of the russian localisation files are stored in
+pub fn decode_text(bytes: &[u8]) -> String {
+    let bytes = if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) { &bytes[3..] } else { bytes };
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| decode_cp1251(b)).collect()
+    }
+}
+
+#[rustfmt::skip]
+const CP1251_HIGH: [u16; 64] = [
+    0x0402, 0x0403, 0x201A, 0x0453, 0x201E, 0x2026, 0x2020, 0x2021,
+    0x20AC, 0x2030, 0x0409, 0x2039, 0x040A, 0x040C, 0x040B, 0x040F,
+    0x0452, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0xFFFD, 0x2122, 0x0459, 0x203A, 0x045A, 0x045C, 0x045B, 0x045F,
+    0x00A0, 0x040E, 0x045E, 0x0408, 0x00A4, 0x0490, 0x00A6, 0x00A7,
+    0x0401, 0x00A9, 0x0404, 0x00AB, 0x00AC, 0x00AD, 0x00AE, 0x0407,
+    0x00B0, 0x00B1, 0x0406, 0x0456, 0x0491, 0x00B5, 0x00B6, 0x00B7,
+    0x0451, 0x2116, 0x0454, 0x00BB, 0x0458, 0x0405, 0x0455, 0x0457,
+];
+
+fn decode_cp1251(b: u8) -> char {
+    let code = match b {
+        0x00..=0x7F => b as u32,
+        0x80..=0xBF => CP1251_HIGH[(b - 0x80) as usize] as u32,
+        _ => 0x0410 + (b - 0xC0) as u32
+    };
+    std::char::from_u32(code).unwrap_or('\u{FFFD}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cues_are_sorted_by_start() {
+        let subtitles = Subtitles::parse(
+            "2\n00:00:02,000 --> 00:00:03,000\nsecond\n\n1\n00:00:00,500 --> 00:00:01,500\nfirst\n"
+        ).unwrap();
+        assert_eq!(subtitles.cue_at(1000.0), Some("first"));
+        assert_eq!(subtitles.cue_at(2500.0), Some("second"));
+        assert_eq!(subtitles.cue_at(1800.0), None);
+    }
+
+    #[test]
+    fn non_finite_timestamps_are_rejected() {
+        assert!(Subtitles::parse("1\nNaN:00:00,000 --> 00:00:01,000\ntext\n").is_err());
+        assert!(Subtitles::parse("1\n00:00:00,000 --> inf:00:01,000\ntext\n").is_err());
+    }
+}